@@ -0,0 +1,250 @@
+use crate::error::RustfoilError;
+use crate::gdrive::FileInfo;
+use crate::result::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Listings of previously scanned folders, keyed by folder ID
+#[derive(Serialize, Deserialize, Default)]
+pub struct ScanCache {
+    folders: HashMap<String, CachedFolder>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedFolder {
+    modified_time: Option<String>,
+    fetched_at: u64,
+    #[serde(flatten)]
+    options: ListingOptions,
+    files: Vec<FileInfo>,
+}
+
+/// How a folder was listed, a cached listing is only reused for the same options
+#[derive(Serialize, Deserialize, Clone)]
+pub struct ListingOptions {
+    pub recursion: bool,
+    #[serde(default)]
+    pub follow_shortcuts: bool,
+    #[serde(default)]
+    pub include_trashed: bool,
+    /// Requested file fields, listings cached before the fields were narrowed have all of them
+    #[serde(default)]
+    pub fields: Option<String>,
+}
+
+impl ListingOptions {
+    /// Whether a listing made with these options has everything a listing with `requested` has
+    fn covers(&self, requested: &ListingOptions) -> bool {
+        let fields_covered = match (&self.fields, &requested.fields) {
+            (None, _) => true,
+            (Some(_), None) => false,
+            (Some(cached), Some(fields)) => {
+                let cached: Vec<&str> = cached.split(',').collect();
+                fields.split(',').all(|field| cached.contains(&field))
+            }
+        };
+
+        self.recursion == requested.recursion
+            && self.follow_shortcuts == requested.follow_shortcuts
+            && self.include_trashed == requested.include_trashed
+            && fields_covered
+    }
+}
+
+impl ScanCache {
+    /// Loads the cache from disk, a missing file results in an empty cache
+    pub fn load(path: &Path) -> Result<ScanCache> {
+        match std::fs::read_to_string(path) {
+            Ok(json) => Ok(serde_json::from_str(json.as_str())
+                .map_err(RustfoilError::invalid_json_file(path))?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(ScanCache::default()),
+            Err(e) => Err(RustfoilError::input_read(path)(e).into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Returns the cached listing if the folder wasn't modified since, it was listed with the same
+    /// `options` & the entry is younger than `ttl`
+    pub fn get(
+        &self,
+        folder_id: &str,
+        modified_time: &Option<String>,
+        options: &ListingOptions,
+        ttl: Duration,
+    ) -> Option<&Vec<FileInfo>> {
+        let folder = self.folders.get(folder_id)?;
+
+        if modified_time.is_none()
+            || &folder.modified_time != modified_time
+            || !folder.options.covers(options)
+            || ScanCache::now().saturating_sub(folder.fetched_at) > ttl.as_secs()
+        {
+            return None;
+        }
+
+        Some(&folder.files)
+    }
+
+    pub fn insert(
+        &mut self,
+        folder_id: &str,
+        modified_time: Option<String>,
+        options: ListingOptions,
+        files: Vec<FileInfo>,
+    ) {
+        self.folders.insert(
+            folder_id.to_string(),
+            CachedFolder {
+                modified_time,
+                fetched_at: ScanCache::now(),
+                options,
+                files,
+            },
+        );
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs())
+            .unwrap_or(0)
+    }
+}
+
+/// IDs of files shared by earlier runs, so they aren't shared again
+#[derive(Serialize, Deserialize, Default)]
+pub struct ShareCache {
+    shared: BTreeSet<String>,
+}
+
+impl ShareCache {
+    /// Loads the cache from disk, a missing file results in an empty cache
+    pub fn load(path: &Path) -> Result<ShareCache> {
+        match std::fs::read_to_string(path) {
+            Ok(json) => Ok(serde_json::from_str(json.as_str())
+                .map_err(RustfoilError::invalid_json_file(path))?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(ShareCache::default()),
+            Err(e) => Err(RustfoilError::input_read(path)(e).into()),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string(self)?)?;
+        Ok(())
+    }
+
+    pub fn contains(&self, file_id: &str) -> bool {
+        self.shared.contains(file_id)
+    }
+
+    pub fn insert(&mut self, file_id: &str) {
+        self.shared.insert(file_id.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    fn options() -> ListingOptions {
+        ListingOptions {
+            recursion: false,
+            follow_shortcuts: false,
+            include_trashed: false,
+            fields: Some("id,name,size".to_string()),
+        }
+    }
+
+    fn cache() -> ScanCache {
+        let mut cache = ScanCache::default();
+        cache.insert(
+            "folder",
+            Some("2020-01-01T00:00:00.000Z".to_string()),
+            options(),
+            vec![FileInfo::new(
+                "id".to_string(),
+                "1".to_string(),
+                "Game.nsp".to_string(),
+                false,
+            )],
+        );
+        cache
+    }
+
+    #[test]
+    fn fresh_cache_short_circuits_the_listing() {
+        let modified_time = Some("2020-01-01T00:00:00.000Z".to_string());
+        let files = cache()
+            .get("folder", &modified_time, &options(), TTL)
+            .map(Vec::len);
+
+        assert_eq!(files, Some(1));
+    }
+
+    #[test]
+    fn stale_cache_is_listed_again() {
+        let cache = cache();
+        let modified_time = Some("2020-01-01T00:00:00.000Z".to_string());
+        let modified = Some("2020-02-01T00:00:00.000Z".to_string());
+        let recursive = ListingOptions {
+            recursion: true,
+            ..options()
+        };
+        let more_fields = ListingOptions {
+            fields: Some("id,name,size,modifiedTime".to_string()),
+            ..options()
+        };
+
+        assert!(cache
+            .get("other", &modified_time, &options(), TTL)
+            .is_none());
+        assert!(cache.get("folder", &modified, &options(), TTL).is_none());
+        assert!(cache.get("folder", &None, &options(), TTL).is_none());
+        assert!(cache
+            .get("folder", &modified_time, &recursive, TTL)
+            .is_none());
+        assert!(cache
+            .get("folder", &modified_time, &more_fields, TTL)
+            .is_none());
+    }
+
+    #[test]
+    fn cache_expires_after_the_ttl() {
+        let mut cache = cache();
+        let modified_time = Some("2020-01-01T00:00:00.000Z".to_string());
+
+        cache.folders.get_mut("folder").unwrap().fetched_at -= TTL.as_secs() + 1;
+
+        assert!(cache
+            .get("folder", &modified_time, &options(), TTL)
+            .is_none());
+    }
+
+    #[test]
+    fn share_cache_hits_saved_ids_only() {
+        let path =
+            std::env::temp_dir().join(format!("rustfoil-share-cache-{}.json", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut cache = ShareCache::load(&path).unwrap();
+        assert!(!cache.contains("1a"));
+
+        cache.insert("1a");
+        cache.save(&path).unwrap();
+
+        let cache = ShareCache::load(&path).unwrap();
+        assert!(cache.contains("1a"));
+        assert!(!cache.contains("2b"));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,84 @@
+use crate::error::{RustfoilError, EXIT_INTERRUPTED};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+
+/// Asks a running service to stop at the next folder or shared file
+pub fn cancel() {
+    CANCELLED.store(true, Ordering::SeqCst);
+}
+
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+/// Fails with `RustfoilError::Cancelled` once `cancel` was called
+pub fn check() -> Result<(), RustfoilError> {
+    if is_cancelled() {
+        return Err(RustfoilError::Cancelled);
+    }
+
+    Ok(())
+}
+
+/// Makes Ctrl-C cancel the run instead of killing the process right away, a second Ctrl-C exits
+/// immediately
+pub fn install_handler() -> Result<(), ctrlc::Error> {
+    ctrlc::set_handler(|| {
+        if CANCELLED.swap(true, Ordering::SeqCst) {
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader};
+    use std::process::{Command, Stdio};
+    use std::time::{Duration, Instant};
+
+    #[cfg(unix)]
+    #[test]
+    fn first_ctrl_c_cancels_and_the_second_exits() {
+        const TEST: &str = "cancel::tests::first_ctrl_c_cancels_and_the_second_exits";
+
+        // The handler is process-wide, so it's only installed in a process running just this test
+        if std::env::var_os("RUSTFOIL_TEST_CTRL_C").is_some() {
+            install_handler().unwrap();
+            println!("ready");
+
+            let started = Instant::now();
+            while !is_cancelled() && started.elapsed() < Duration::from_secs(10) {
+                std::thread::sleep(Duration::from_millis(10));
+            }
+            assert!(check().is_err());
+            println!("cancelled");
+
+            std::thread::sleep(Duration::from_secs(10));
+            panic!("the second Ctrl-C didn't exit");
+        }
+
+        let mut child = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", TEST, "--test-threads=1", "--nocapture"])
+            .env("RUSTFOIL_TEST_CTRL_C", "1")
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+        let interrupt = || {
+            let status = Command::new("kill")
+                .args(["-INT", child.id().to_string().as_str()])
+                .status()
+                .unwrap();
+            assert!(status.success());
+        };
+
+        for marker in &["ready", "cancelled"] {
+            assert!(lines.any(|line| line.unwrap().ends_with(marker)));
+            interrupt();
+        }
+
+        assert_eq!(child.wait().unwrap().code(), Some(EXIT_INTERRUPTED));
+    }
+}
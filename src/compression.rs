@@ -1,16 +1,20 @@
 use crate::result::Result;
 use core::fmt;
-use flate2::write::ZlibEncoder;
+use flate2::read::{GzDecoder, ZlibDecoder};
+use flate2::write::{GzEncoder, ZlibEncoder};
 use flate2::Compression;
-use std::io::Write;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use structopt::StructOpt;
+use std::time::{Duration, Instant};
 
-#[derive(StructOpt, Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy)]
 pub enum CompressionFlag {
     Off = 0x00,
-    ZSTD = 0x0D,
+    Zstd = 0x0D,
     Zlib = 0x0E,
+    /// Tries every compression & keeps the smallest result, never written as a flag itself
+    Auto = 0xFF,
 }
 
 impl FromStr for CompressionFlag {
@@ -18,10 +22,19 @@ impl FromStr for CompressionFlag {
 
     fn from_str(compression: &str) -> std::result::Result<Self, Self::Err> {
         match compression.to_lowercase().as_ref() {
-            "off" => Ok(CompressionFlag::Off),
-            "zstd" => Ok(CompressionFlag::ZSTD),
-            "zlib" => Ok(CompressionFlag::Zlib),
-            _ => Err(format!("Invalid compression: {}", compression)),
+            "off" | "none" => Ok(CompressionFlag::Off),
+            "zstd" | "zst" => Ok(CompressionFlag::Zstd),
+            "zlib" | "zz" => Ok(CompressionFlag::Zlib),
+            "auto" => Ok(CompressionFlag::Auto),
+            // Tinfoil only reads raw zlib streams, gzip is only used by --gzip-sidecar
+            "gzip" | "gz" => Err(format!(
+                "Invalid compression: {}, Tinfoil doesn't support gzip, use zlib instead",
+                compression
+            )),
+            _ => Err(format!(
+                "Invalid compression: {}, valid choices are off (none), zstd (zst), zlib (zz) & auto",
+                compression
+            )),
         }
     }
 }
@@ -33,23 +46,356 @@ impl fmt::Display for CompressionFlag {
             "{}",
             match self {
                 CompressionFlag::Off => "off".to_string(),
-                CompressionFlag::ZSTD => "zstd".to_string(),
+                CompressionFlag::Zstd => "zstd".to_string(),
                 CompressionFlag::Zlib => "zlib".to_string(),
+                CompressionFlag::Auto => "auto".to_string(),
             }
         )
     }
 }
 
+/// Settings only used by zstd
+#[derive(Debug, Clone, Copy)]
+pub struct ZstdOptions<'a> {
+    pub dictionary: Option<&'a [u8]>,
+    /// Worker threads, 1 compresses on the calling thread
+    pub threads: u32,
+}
+
+impl Default for ZstdOptions<'_> {
+    fn default() -> Self {
+        ZstdOptions {
+            dictionary: None,
+            threads: 1,
+        }
+    }
+}
+
 impl CompressionFlag {
-    pub fn compress(&self, data: &str) -> Result<Vec<u8>> {
+    /// Compression of a Tinfoil header flag, `None` for unknown compressions
+    pub fn from_flag(flag: u8) -> Option<CompressionFlag> {
+        match flag & 0x0F {
+            0x00 => Some(CompressionFlag::Off),
+            0x0D => Some(CompressionFlag::Zstd),
+            0x0E => Some(CompressionFlag::Zlib),
+            _ => None,
+        }
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
         match &self {
-            CompressionFlag::Off => Ok(data.as_bytes().to_vec()),
-            CompressionFlag::ZSTD => Ok(zstd::block::compress(data.as_bytes(), 22)?),
+            CompressionFlag::Off => Ok(data.to_vec()),
+            CompressionFlag::Zstd => Ok(zstd::stream::decode_all(data)?),
+            CompressionFlag::Zlib => {
+                let mut decompressed = Vec::new();
+                ZlibDecoder::new(data).read_to_end(&mut decompressed)?;
+                Ok(decompressed)
+            }
+            CompressionFlag::Auto => Err(anyhow::anyhow!(
+                "Auto has to be resolved to a compression before decompressing"
+            )),
+        }
+    }
+
+    pub fn compress(&self, data: &str) -> Result<Vec<u8>> {
+        self.compress_with_options(data, ZstdOptions::default())
+    }
+
+    pub fn compress_with_options(&self, data: &str, options: ZstdOptions) -> Result<Vec<u8>> {
+        self.compress_resolved(data, options)
+            .map(|(_, compressed)| compressed)
+    }
+
+    /// Compresses the data & returns the compression actually used, which only differs for `Auto`
+    pub fn compress_resolved(
+        &self,
+        data: &str,
+        options: ZstdOptions,
+    ) -> Result<(CompressionFlag, Vec<u8>)> {
+        if let CompressionFlag::Auto = self {
+            let mut smallest: Option<(CompressionFlag, Vec<u8>)> = None;
+
+            for compression in &[
+                CompressionFlag::Zstd,
+                CompressionFlag::Zlib,
+                CompressionFlag::Off,
+            ] {
+                let compressed = compression.compress_with_options(data, options)?;
+
+                if smallest
+                    .as_ref()
+                    .is_none_or(|(_, best)| compressed.len() < best.len())
+                {
+                    smallest = Some((*compression, compressed));
+                }
+            }
+
+            return Ok(smallest.unwrap());
+        }
+
+        let compressed = match &self {
+            CompressionFlag::Off => data.as_bytes().to_vec(),
+            CompressionFlag::Zstd if options.threads > 1 => zstd_multithreaded(data, options)?,
+            CompressionFlag::Zstd => match options.dictionary {
+                Some(dictionary) => zstd::block::Compressor::with_dict(dictionary.to_vec())
+                    .compress(data.as_bytes(), 22)?,
+                None => zstd::block::compress(data.as_bytes(), 22)?,
+            },
             CompressionFlag::Zlib => {
                 let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
                 encoder.write_all(data.as_ref())?;
+                encoder.finish()?
+            }
+            CompressionFlag::Auto => unreachable!("Auto is resolved above"),
+        };
+
+        Ok((*self, compressed))
+    }
+}
+
+/// Compresses with several worker threads in one call, unlike the streaming encoder this writes the
+/// content size into the frame header like the single threaded compression, so one-shot decoders
+/// can size their buffer from it
+fn zstd_multithreaded(data: &str, options: ZstdOptions) -> Result<Vec<u8>> {
+    let error = |code: usize| {
+        anyhow::anyhow!(
+            "zstd compression failed: {}",
+            zstd_safe::get_error_name(code)
+        )
+    };
+
+    let mut cctx = zstd_safe::create_cctx();
+    zstd_safe::cctx_set_parameter(&mut cctx, zstd_safe::CParameter::CompressionLevel(22))
+        .map_err(error)?;
+    zstd_safe::cctx_set_parameter(&mut cctx, zstd_safe::CParameter::NbWorkers(options.threads))
+        .map_err(error)?;
+
+    if let Some(dictionary) = options.dictionary {
+        zstd_safe::cctx_load_dictionary(&mut cctx, dictionary).map_err(error)?;
+    }
+
+    let mut compressed = vec![0; zstd_safe::compress_bound(data.len())];
+    let size = zstd_safe::compress2(&mut cctx, &mut compressed, data.as_bytes()).map_err(error)?;
+    compressed.truncate(size);
+
+    Ok(compressed)
+}
+
+/// Compressions & levels compared by the compression benchmark
+pub const BENCHMARK_LEVELS: [(CompressionFlag, u32); 6] = [
+    (CompressionFlag::Off, 0),
+    (CompressionFlag::Zlib, 6),
+    (CompressionFlag::Zlib, 9),
+    (CompressionFlag::Zstd, 3),
+    (CompressionFlag::Zstd, 19),
+    (CompressionFlag::Zstd, 22),
+];
+
+impl CompressionFlag {
+    /// Compresses at the given level instead of the highest one, the level is ignored for `Off`
+    pub fn compress_with_level(&self, data: &str, level: u32) -> Result<Vec<u8>> {
+        match &self {
+            CompressionFlag::Zstd => Ok(zstd::block::compress(data.as_bytes(), level as i32)?),
+            CompressionFlag::Zlib => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(level));
+                encoder.write_all(data.as_ref())?;
                 Ok(encoder.finish()?)
             }
+            _ => self.compress(data),
         }
     }
 }
+
+/// Result of one compression of the benchmark
+pub struct BenchmarkRow {
+    /// Compression & level, e.g. `zstd 19`
+    pub name: String,
+    pub size: usize,
+    pub elapsed: Duration,
+}
+
+/// Compresses the data with every compression & level in `BENCHMARK_LEVELS`, one row each
+pub fn benchmark(data: &str) -> Result<Vec<BenchmarkRow>> {
+    BENCHMARK_LEVELS
+        .iter()
+        .map(|(compression, level)| {
+            let started = Instant::now();
+            let compressed = compression.compress_with_level(data, *level)?;
+
+            Ok(BenchmarkRow {
+                name: match compression {
+                    CompressionFlag::Off => compression.to_string(),
+                    _ => format!("{} {}", compression, level),
+                },
+                size: compressed.len(),
+                elapsed: started.elapsed(),
+            })
+        })
+        .collect()
+}
+
+/// Trains a zstd dictionary of at most `max_size` bytes from the given sample files
+pub fn train_dictionary(samples: &[PathBuf], max_size: usize) -> Result<Vec<u8>> {
+    Ok(zstd::dict::from_files(samples, max_size)?)
+}
+
+/// Gzip compresses arbitrary bytes, independent of Tinfoil's own compression
+pub fn gzip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+/// Magic bytes every gzip stream starts with
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+pub fn is_gzip(data: &[u8]) -> bool {
+    data.starts_with(&GZIP_MAGIC)
+}
+
+/// Reads a text file, transparently decompressing it if it's gzip-compressed
+pub fn read_to_string_maybe_gzip(path: &Path) -> io::Result<String> {
+    decode_maybe_gzip(std::fs::read(path)?)
+}
+
+/// Decodes text contents, decompressing them first if they're gzip-compressed
+pub fn decode_maybe_gzip(data: Vec<u8>) -> io::Result<String> {
+    if !is_gzip(&data) {
+        return String::from_utf8(data).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+    }
+
+    let mut contents = String::new();
+    GzDecoder::new(data.as_slice()).read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const JSON: &str = r#"{"files":[{"url":"gdrive:1a#Game%20[0100000000010000][v0].nsp","size":1024},{"url":"gdrive:2b#Game%20[0100000000010800][v65536].nsp","size":2048}]}"#;
+
+    fn decode_with_dictionary(data: &[u8], dictionary: &[u8]) -> String {
+        let mut decoded = String::new();
+        zstd::stream::Decoder::with_dictionary(data, dictionary)
+            .unwrap()
+            .read_to_string(&mut decoded)
+            .unwrap();
+        decoded
+    }
+
+    #[test]
+    fn zstd_dictionary_round_trips() {
+        let dictionary =
+            br#"{"files":[{"url":"gdrive:#Game%20[0100000000010000][v0].nsp","size":}]}"#;
+
+        for threads in [1, 2] {
+            let options = ZstdOptions {
+                dictionary: Some(dictionary),
+                threads,
+            };
+            let compressed = CompressionFlag::Zstd
+                .compress_with_options(JSON, options)
+                .unwrap();
+
+            assert_eq!(decode_with_dictionary(&compressed, dictionary), JSON);
+        }
+    }
+
+    #[test]
+    fn zstd_frames_carry_the_content_size_for_one_shot_decoders() {
+        let dictionary =
+            br#"{"files":[{"url":"gdrive:#Game%20[0100000000010000][v0].nsp","size":}]}"#;
+
+        for threads in [1, 2] {
+            for dictionary in [None, Some(&dictionary[..])] {
+                let compressed = CompressionFlag::Zstd
+                    .compress_with_options(
+                        JSON,
+                        ZstdOptions {
+                            dictionary,
+                            threads,
+                        },
+                    )
+                    .unwrap();
+
+                let content_size = zstd_safe::get_frame_content_size(&compressed);
+                assert_eq!(content_size, JSON.len() as u64, "{} threads", threads);
+
+                // Decoded in one call into a buffer of exactly the announced size
+                let mut decoded = vec![0; content_size as usize];
+                let size = match dictionary {
+                    Some(dictionary) => zstd_safe::decompress_using_dict(
+                        &mut zstd_safe::create_dctx(),
+                        &mut decoded,
+                        &compressed,
+                        dictionary,
+                    ),
+                    None => zstd_safe::decompress(&mut decoded, &compressed),
+                }
+                .unwrap();
+                assert_eq!(&decoded[..size], JSON.as_bytes());
+            }
+        }
+    }
+
+    #[test]
+    fn parses_compression_aliases() {
+        for (value, expected) in [
+            ("off", "off"),
+            ("none", "off"),
+            ("ZSTD", "zstd"),
+            ("zst", "zstd"),
+            ("zlib", "zlib"),
+            ("zz", "zlib"),
+            ("auto", "auto"),
+        ] {
+            let flag: CompressionFlag = value.parse().unwrap();
+            assert_eq!(flag.to_string(), expected);
+        }
+
+        let gzip = "gzip".parse::<CompressionFlag>().unwrap_err();
+        assert!(gzip.contains("use zlib instead"));
+
+        for value in ["lzma", "", "zstd "] {
+            let error = value.parse::<CompressionFlag>().unwrap_err();
+            assert!(error.contains("valid choices are off (none), zstd (zst), zlib (zz) & auto"));
+        }
+    }
+
+    #[test]
+    fn auto_is_never_larger_than_the_best_fixed_compression() {
+        let large = JSON.repeat(200);
+
+        for data in ["", "a", JSON, large.as_str()] {
+            let (used, compressed) = CompressionFlag::Auto
+                .compress_resolved(data, ZstdOptions::default())
+                .unwrap();
+            let best = [
+                CompressionFlag::Off,
+                CompressionFlag::Zstd,
+                CompressionFlag::Zlib,
+            ]
+            .iter()
+            .map(|compression| compression.compress(data).unwrap().len())
+            .min()
+            .unwrap();
+
+            assert!(compressed.len() <= best, "{} for {:?}", used, data);
+            assert_eq!(used.decompress(&compressed).unwrap(), data.as_bytes());
+        }
+    }
+
+    #[test]
+    fn benchmark_has_one_row_per_compression() {
+        let rows = benchmark(JSON).unwrap();
+        let names: Vec<&str> = rows.iter().map(|row| row.name.as_str()).collect();
+
+        assert_eq!(
+            names,
+            vec!["off", "zlib 6", "zlib 9", "zstd 3", "zstd 19", "zstd 22"]
+        );
+        assert_eq!(rows[0].size, JSON.len());
+        assert!(rows[1..].iter().all(|row| row.size < JSON.len()));
+    }
+}
@@ -0,0 +1,362 @@
+use crate::compression::CompressionFlag;
+use crate::encryption::RsaPadding;
+use crate::index::{DedupeStrategy, IndexSchema};
+use crate::logging::{LogFormat, LogLevel};
+use crate::name::TitleIdStyle;
+use chrono::{DateTime, Utc};
+use std::path::PathBuf;
+
+/// Google account with its own credentials & token, referred to by `name`
+#[derive(Debug, Clone)]
+pub struct Account {
+    pub name: String,
+    pub credentials: PathBuf,
+    pub token: PathBuf,
+}
+
+/// Everything needed to generate an index, the CLI builds this from its arguments
+#[derive(Debug, Clone)]
+pub struct RustfoilConfig {
+    /// Folder IDs or share URLs of Google Drive folders to scan
+    pub folder_ids: Vec<String>,
+    /// URL of a HTTP directory listing or JSON manifest to scan
+    pub http_url: Option<String>,
+    /// Reads `URL<TAB>size[<TAB>name]` lines from stdin instead of scanning
+    pub from_stdin: bool,
+    /// Path to Google Application Credentials
+    pub credentials: PathBuf,
+    /// Path to Google OAuth2.0 User Token
+    pub token: PathBuf,
+    /// Environment variable containing the Google Application Credentials JSON
+    pub credentials_env: String,
+    /// Environment variable containing the Google OAuth2.0 User Token JSON
+    pub token_env: String,
+    /// Additional Google accounts, used for the folders mapped to them in `folder_accounts`
+    pub accounts: Vec<Account>,
+    /// Folder IDs & the name of the account scanning them, other folders use the default account
+    pub folder_accounts: Vec<(String, String)>,
+    /// Path to output index file, `-` writes it to stdout
+    pub output_path: PathBuf,
+    /// Directory to write the index file to, named after `name_pattern`, replaces `output_path`
+    pub output_dir: Option<PathBuf>,
+    /// File name of the index inside `output_dir`, supports the placeholders of `success`
+    pub name_pattern: String,
+    /// Overwrites existing index files instead of refusing to write them
+    pub overwrite: bool,
+    /// Share all files inside the index file
+    pub share_files: bool,
+    /// Number of files shared in parallel
+    pub share_concurrency: usize,
+    /// Maximum Drive API requests in flight at once, shared by all stages & accounts
+    pub max_concurrent_requests: Option<usize>,
+    /// Shares files again even when they're already shared
+    pub reshare: bool,
+    /// Path to a cache of file IDs shared by earlier runs
+    pub share_cache: Option<PathBuf>,
+    /// Forgets the file IDs in the share cache while still updating it
+    pub refresh_share_cache: bool,
+    /// Scans for files only in top directory for each Folder ID
+    pub no_recursion: bool,
+    /// Prefix used for Google Drive file URLs in the index, followed by the file ID
+    pub url_prefix: String,
+    /// Per extension (without the dot) prefixes used instead of `url_prefix`
+    pub url_map: Vec<(String, String)>,
+    /// Path to a cache of folder listings, only used for scans without recursion & sharing
+    pub cache: Option<PathBuf>,
+    /// Maximum age in seconds of cached folder listings
+    pub cache_ttl: u64,
+    /// Ignores cached folder listings while still updating the cache
+    pub no_cache: bool,
+    /// Logs and skips folders which fail to scan instead of aborting
+    pub continue_on_error: bool,
+    /// Adds files without valid Title ID
+    pub add_nsw_files_without_title_id: bool,
+    /// Regex used to find the Title ID in file names, the first group captures the ID. It's
+    /// matched against the plain name, not the percent-encoded one
+    pub title_id_pattern: Option<String>,
+    /// Naming scheme Title IDs are extracted from when no `title_id_pattern` is set
+    pub title_id_style: TitleIdStyle,
+    /// Adds files without an allowed extension
+    pub add_non_nsw_files: bool,
+    /// Skips all filters applied to scanned files
+    pub no_filter: bool,
+    /// Adds Google-native files like Docs or Sheets
+    pub include_google_native: bool,
+    /// Resolves Drive shortcuts to the files they point to
+    pub follow_shortcuts: bool,
+    /// Lists trashed files too
+    pub include_trashed: bool,
+    /// Logs trashed files passing the filters after scanning
+    pub report_trashed: bool,
+    /// Skips files not owned by the authenticated user
+    pub owner_only: bool,
+    /// Only adds the first N scanned files to the index
+    pub max_files: Option<usize>,
+    /// Writes the index even if no files were found, instead of failing
+    pub allow_empty: bool,
+    /// File extensions to add to the index
+    pub extensions: Vec<String>,
+    /// Skips files smaller than this size in bytes
+    pub min_size: Option<u64>,
+    /// Skips files larger than this size in bytes
+    pub max_size: Option<u64>,
+    /// Caps the summed size of the indexed files, dropping the oldest ones
+    pub max_total_size: Option<u64>,
+    /// Only files modified at or after this point in time are kept
+    pub since: Option<DateTime<Utc>>,
+    /// Keeps files without a known modification date when filtering by `since`
+    pub include_unknown_mtime: bool,
+    /// Aborts instead of skipping files with a missing or invalid size
+    pub strict_size: bool,
+    /// Warns about Title IDs which appear in more than one scanned file
+    pub warn_duplicates: bool,
+    /// Removes files duplicating an earlier one, keeping the first
+    pub dedupe_strategy: Option<DedupeStrategy>,
+    /// Path to a file of Title IDs, only files with one of these are kept
+    pub title_id_allowlist: Option<PathBuf>,
+    /// Path to a file of Title IDs, files with one of these are left out, even if allowlisted
+    pub title_id_denylist: Option<PathBuf>,
+    /// Template for the file names shown in Tinfoil
+    pub name_template: Option<String>,
+    /// Logs the original & encoded name of every indexed file at trace level
+    pub debug_names: bool,
+    /// Drive file property holding the version, preferred over the version parsed from the file name
+    pub version_from_metadata: Option<String>,
+    /// Prefixes the file names shown in Tinfoil with their folder path relative to the scanned folder
+    pub include_path: bool,
+    /// Lowercases the extension of the names in the index
+    pub normalize_extensions: bool,
+    /// Removes region & language tags from the names in the index
+    pub strip_region_tags: bool,
+    /// Regex replacing the default tags removed by `strip_region_tags`
+    pub region_tag_regex: Option<String>,
+    /// Success message shown if the index is successfully read by Tinfoil
+    pub success: Option<String>,
+    /// Adds `generatedAt` & `generator` to the index
+    pub embed_metadata: bool,
+    /// Referrer to prevent others from hotlinking
+    pub referrer: Option<String>,
+    /// Google API key to be used with all gdrive:/ requests
+    pub google_api_key: Option<String>,
+    /// 1Fichier API keys to be used with all 1f:/ requests
+    pub one_fichier_keys: Option<Vec<String>>,
+    /// Aborts instead of warning when a 1Fichier key looks malformed
+    pub strict_keys: bool,
+    /// Custom HTTP headers Tinfoil should send with its requests
+    pub headers: Option<Vec<String>>,
+    /// Name of the header & the fallback CDN URL added to the headers in it, for the server
+    /// behind the index to act on
+    pub cdn_fallback: Option<(String, String)>,
+    /// URLs of other indexes Tinfoil should load as well
+    pub directories: Option<Vec<String>>,
+    /// Minimum Tinfoil version to load the index
+    pub min_version: Option<f64>,
+    /// Themes to blacklist based on their hash
+    pub theme_blacklist: Option<Vec<String>>,
+    /// Themes to whitelist based on their hash
+    pub theme_whitelist: Option<Vec<String>>,
+    /// Custom theme error message
+    pub theme_error: Option<String>,
+    /// Path to RSA Public Key to encrypt the AES-128-ECB key with
+    pub public_key: Option<PathBuf>,
+    /// Padding of the RSA encrypted session key
+    pub rsa_padding: RsaPadding,
+    /// Fixed AES-128 session key as hex instead of a random one
+    pub aes_key: Option<String>,
+    /// Sorts the files by URL so the same files always produce the same output
+    pub deterministic: bool,
+    /// Shares the index file that is uploaded to Google Drive
+    pub share_index: bool,
+    /// Folder the index file should be uploaded to
+    pub upload_folder_id: Option<String>,
+    /// If the index file should be uploaded to My Drive
+    pub upload_my_drive: bool,
+    /// Continues an interrupted upload from the session stored next to the index
+    pub resume_upload: bool,
+    /// S3-compatible bucket the index files are uploaded to
+    pub s3_bucket: Option<String>,
+    /// Object key of the index in the S3 bucket, split parts are stored next to it
+    pub s3_key: Option<String>,
+    /// Region of the S3 bucket
+    pub s3_region: String,
+    /// Endpoint of an S3-compatible service, defaults to AWS
+    pub s3_endpoint: Option<String>,
+    /// Splits the index into parts whose uncompressed JSON stays below this size in bytes
+    pub split_size: Option<u64>,
+    /// Writes a separate index per folder ID, its ID is appended to the file name
+    pub per_folder: bool,
+    /// Base URL the split parts are reachable at
+    pub split_base_url: Option<String>,
+    /// Additionally writes a gzip compressed copy of the index file next to it
+    pub gzip_sidecar: bool,
+    /// Additionally writes the SHA-256 of the index file next to it
+    pub checksum_sidecar: bool,
+    /// Warns when the index has more files than this
+    pub warn_threshold: usize,
+    /// Path a plain text table of the indexed files is written to
+    pub report: Option<PathBuf>,
+    /// Writes the URL of every indexed file to this path, one per line
+    pub urls_only: Option<PathBuf>,
+    /// Skips writing the index file
+    pub no_index: bool,
+    /// Which compression should be used for the index file
+    pub compression: CompressionFlag,
+    /// Key naming of the written index
+    pub schema: IndexSchema,
+    /// Number of threads used for zstd compression, 0 uses one per CPU
+    pub compression_threads: u32,
+    /// Path to a zstd dictionary used when compressing with zstd
+    pub zstd_dict: Option<PathBuf>,
+    /// Path the dictionary trained from `dict_samples` is written to
+    pub train_dict: Option<PathBuf>,
+    /// Uncompressed index JSON files to train the zstd dictionary from
+    pub dict_samples: Vec<PathBuf>,
+    /// Index file to check for problems
+    pub check: Option<PathBuf>,
+    /// Prints the files of the index as JSON lines to stdout instead of writing it
+    pub list: bool,
+    /// Old & new index file to compare instead of generating one
+    pub diff: Option<(PathBuf, PathBuf)>,
+    /// Path to the RSA Private Key used to decrypt encrypted index files when checking or comparing
+    pub private_key: Option<PathBuf>,
+    /// Index (JSON or Tinfoil file) to benchmark the compressions on instead of generating one
+    pub benchmark_compression: Option<PathBuf>,
+    /// Only authorizes with Google Drive & reports the account's email address
+    pub verify_credentials: bool,
+    /// Logs a rough file count of every folder instead of generating an index
+    pub estimate: bool,
+    /// If OAuth should be done headless
+    pub headless: bool,
+    /// Authorize via a device code
+    pub auth_device_code: bool,
+    /// Seconds to wait for the device code to be authorized
+    pub auth_timeout: u64,
+    /// Timeout in seconds for connecting to & reading from Google Drive
+    pub timeout: u64,
+    /// HTTP proxy for the Drive requests, `HTTPS_PROXY`/`HTTP_PROXY` are used if not set
+    pub proxy: Option<String>,
+    /// Format of log lines
+    pub log_format: LogFormat,
+    /// Minimum level of messages to log
+    pub log_level: LogLevel,
+}
+
+impl Default for RustfoilConfig {
+    fn default() -> Self {
+        RustfoilConfig {
+            folder_ids: Vec::new(),
+            http_url: None,
+            from_stdin: false,
+            credentials: PathBuf::from("credentials.json"),
+            token: PathBuf::from("token.json"),
+            credentials_env: "RUSTFOIL_CREDENTIALS".to_string(),
+            token_env: "RUSTFOIL_TOKEN".to_string(),
+            accounts: Vec::new(),
+            folder_accounts: Vec::new(),
+            output_path: PathBuf::from("index.tfl"),
+            output_dir: None,
+            name_pattern: "index.tfl".to_string(),
+            overwrite: false,
+            share_files: false,
+            share_concurrency: 8,
+            max_concurrent_requests: None,
+            reshare: false,
+            share_cache: None,
+            refresh_share_cache: false,
+            no_recursion: false,
+            url_prefix: "gdrive:".to_string(),
+            url_map: Vec::new(),
+            cache: None,
+            cache_ttl: 86400,
+            no_cache: false,
+            continue_on_error: false,
+            add_nsw_files_without_title_id: false,
+            title_id_pattern: None,
+            title_id_style: TitleIdStyle::Bracket,
+            add_non_nsw_files: false,
+            no_filter: false,
+            include_google_native: false,
+            follow_shortcuts: false,
+            include_trashed: false,
+            report_trashed: false,
+            owner_only: false,
+            max_files: None,
+            allow_empty: false,
+            extensions: ["nsp", "nsz", "xci", "xcz", "nro"]
+                .iter()
+                .map(|extension| extension.to_string())
+                .collect(),
+            min_size: None,
+            max_size: None,
+            max_total_size: None,
+            since: None,
+            include_unknown_mtime: false,
+            strict_size: false,
+            warn_duplicates: false,
+            dedupe_strategy: None,
+            title_id_allowlist: None,
+            title_id_denylist: None,
+            name_template: None,
+            debug_names: false,
+            version_from_metadata: None,
+            include_path: false,
+            normalize_extensions: false,
+            strip_region_tags: false,
+            region_tag_regex: None,
+            success: None,
+            embed_metadata: false,
+            referrer: None,
+            google_api_key: None,
+            one_fichier_keys: None,
+            strict_keys: false,
+            headers: None,
+            cdn_fallback: None,
+            directories: None,
+            min_version: None,
+            theme_blacklist: None,
+            theme_whitelist: None,
+            theme_error: None,
+            public_key: None,
+            rsa_padding: RsaPadding::Oaep,
+            aes_key: None,
+            deterministic: false,
+            share_index: false,
+            upload_folder_id: None,
+            upload_my_drive: false,
+            resume_upload: false,
+            s3_bucket: None,
+            s3_key: None,
+            s3_region: "us-east-1".to_string(),
+            s3_endpoint: None,
+            split_size: None,
+            per_folder: false,
+            split_base_url: None,
+            gzip_sidecar: false,
+            checksum_sidecar: false,
+            warn_threshold: 50000,
+            report: None,
+            urls_only: None,
+            no_index: false,
+            compression: CompressionFlag::Zstd,
+            schema: IndexSchema::V1,
+            compression_threads: 1,
+            zstd_dict: None,
+            train_dict: None,
+            dict_samples: Vec::new(),
+            check: None,
+            list: false,
+            diff: None,
+            private_key: None,
+            benchmark_compression: None,
+            verify_credentials: false,
+            estimate: false,
+            headless: false,
+            auth_device_code: false,
+            auth_timeout: 300,
+            timeout: 30,
+            proxy: None,
+            log_format: LogFormat::Text,
+            log_level: LogLevel::Info,
+        }
+    }
+}
@@ -1,14 +1,18 @@
+use crate::error::RustfoilError;
 use aes::Aes128;
-use block_modes::block_padding::ZeroPadding;
+use block_modes::block_padding::{NoPadding, ZeroPadding};
 use block_modes::{BlockMode, Ecb};
-use rand::rngs::OsRng;
-use rand::RngCore;
-use rsa::{pem, PaddingScheme, PublicKey, RSAPublicKey};
+use core::fmt;
+use rand::rngs::{OsRng, StdRng};
+use rand::{RngCore, SeedableRng};
+use rsa::{pem, PaddingScheme, PublicKey, PublicKeyParts, RSAPrivateKey, RSAPublicKey};
+use sha2::{Digest, Sha256};
 use std::convert::TryFrom;
-use std::fs::File;
-use std::io::{BufReader, Read};
 use std::path::Path;
+use std::str::FromStr;
 
+/// Tinfoil only knows a single encrypted flag, which always means AES-128-ECB with an RSA-OAEP
+/// wrapped session key. There is no header value for other modes like CBC, so none are offered
 #[derive(Debug, Clone, Copy)]
 pub enum EncryptionFlag {
     NoEncrypt = 0x00,
@@ -16,35 +20,356 @@ pub enum EncryptionFlag {
 }
 
 impl EncryptionFlag {
-    pub fn encrypt(&self, data: Vec<u8>, file: &Path) -> crate::result::Result<(Vec<u8>, Vec<u8>)> {
-        // Basic Idea, Generate random aes key, encrypt data with aes key via ecb, encrypt aes key with pubkey, send encrypted data & encrypted eas key
+    /// Reads the encryption from the high nibble of an index file's flag byte
+    pub fn from_flag(flag: u8) -> Option<EncryptionFlag> {
+        match flag & 0xF0 {
+            0x00 => Some(EncryptionFlag::NoEncrypt),
+            0xF0 => Some(EncryptionFlag::Encrypt),
+            _ => None,
+        }
+    }
+}
+
+/// How the session key is padded before it is encrypted with RSA, Tinfoil itself expects OAEP
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RsaPadding {
+    /// RSA-OAEP with SHA-256
+    Oaep,
+    Pkcs1v15,
+}
+
+impl RsaPadding {
+    fn scheme(self) -> PaddingScheme {
+        match self {
+            RsaPadding::Oaep => PaddingScheme::new_oaep::<Sha256>(),
+            RsaPadding::Pkcs1v15 => PaddingScheme::new_pkcs1v15_encrypt(),
+        }
+    }
+}
+
+impl FromStr for RsaPadding {
+    type Err = String;
+
+    fn from_str(padding: &str) -> std::result::Result<Self, Self::Err> {
+        match padding.to_lowercase().as_ref() {
+            "oaep" => Ok(RsaPadding::Oaep),
+            "pkcs1v15" | "pkcs1" => Ok(RsaPadding::Pkcs1v15),
+            _ => Err(format!(
+                "Invalid RSA padding: {}, valid choices are oaep & pkcs1v15",
+                padding
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RsaPadding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RsaPadding::Oaep => write!(f, "oaep"),
+            RsaPadding::Pkcs1v15 => write!(f, "pkcs1v15"),
+        }
+    }
+}
+
+/// Size of the wrapped session key in the Tinfoil header, so only 2048 bit RSA keys fit
+pub const WRAPPED_KEY_SIZE: usize = 0x100;
 
-        let mut random_aes_key = [0u8; 16];
+/// Reads a PEM or DER (PKCS#8 or PKCS#1) encoded RSA public key, PEM is detected by its header
+pub fn read_public_key(path: &Path) -> crate::result::Result<RSAPublicKey> {
+    let bytes = std::fs::read(path).map_err(RustfoilError::input_read(path))?;
 
-        OsRng.fill_bytes(&mut random_aes_key);
+    if String::from_utf8_lossy(&bytes).contains("-----BEGIN") {
+        return Ok(RSAPublicKey::try_from(pem::parse(bytes)?)?);
+    }
 
-        let pub_key_file = File::open(file)?;
-        let mut buf_reader = BufReader::new(pub_key_file);
-        let mut pub_key_str = String::new();
+    RSAPublicKey::from_pkcs8(&bytes)
+        .or_else(|_| RSAPublicKey::from_pkcs1(&bytes))
+        .map_err(|_| RustfoilError::UnsupportedPublicKey(path.to_owned()).into())
+}
 
-        buf_reader.read_to_string(&mut pub_key_str)?;
+/// Reads a PEM encoded RSA private key
+pub fn read_private_key(path: &Path) -> crate::result::Result<RSAPrivateKey> {
+    let bytes = std::fs::read(path).map_err(RustfoilError::input_read(path))?;
 
-        let public_key = RSAPublicKey::try_from(pem::parse(pub_key_str)?)?;
+    Ok(RSAPrivateKey::try_from(pem::parse(bytes)?)?)
+}
 
-        let iv = Default::default();
+/// Size of the AES-128 session key Tinfoil expects
+pub const AES_KEY_SIZE: usize = 16;
 
-        let ecb = Ecb::<Aes128, ZeroPadding>::new_var(random_aes_key.as_ref(), iv)?;
+/// Generates a random AES session key
+pub fn generate_aes_key() -> [u8; AES_KEY_SIZE] {
+    let mut key = [0u8; AES_KEY_SIZE];
+    OsRng.fill_bytes(&mut key);
+    key
+}
 
-        let encrypted_data = ecb.encrypt_vec(data.as_slice());
+/// Parses an AES session key given as hex
+pub fn parse_aes_key(hex: &str) -> Result<[u8; AES_KEY_SIZE], RustfoilError> {
+    let hex = hex.trim();
 
-        let mut rng = OsRng;
+    if hex.len() != AES_KEY_SIZE * 2 || !hex.is_ascii() {
+        return Err(RustfoilError::InvalidAesKey);
+    }
+
+    let mut key = [0u8; AES_KEY_SIZE];
+
+    for (i, byte) in key.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[i * 2..i * 2 + 2], 16)
+            .map_err(|_| RustfoilError::InvalidAesKey)?;
+    }
+
+    Ok(key)
+}
+
+/// Encrypts the data with AES-128-ECB, `data` has to be padded to the AES block size already
+pub fn encrypt_aes(data: &[u8], key: &[u8; AES_KEY_SIZE]) -> crate::result::Result<Vec<u8>> {
+    let ecb = Ecb::<Aes128, ZeroPadding>::new_var(key.as_ref(), Default::default())?;
+
+    Ok(ecb.encrypt_vec(data))
+}
+
+/// Wraps the session key with RSA, the result is as long as the RSA modulus
+pub fn wrap_key(
+    key: &[u8],
+    public_key: &RSAPublicKey,
+    padding: RsaPadding,
+) -> crate::result::Result<Vec<u8>> {
+    Ok(public_key.encrypt(&mut OsRng, padding.scheme(), key)?)
+}
+
+/// Same as `wrap_key`, but the padding is seeded from the key so the result is always the same
+pub fn wrap_key_deterministic(
+    key: &[u8],
+    public_key: &RSAPublicKey,
+    padding: RsaPadding,
+) -> crate::result::Result<Vec<u8>> {
+    let mut rng = StdRng::from_seed(Sha256::digest(key).into());
+
+    Ok(public_key.encrypt(&mut rng, padding.scheme(), key)?)
+}
+
+/// Unwraps the session key with the private key & decrypts the data with it, the data keeps its
+/// padding
+pub fn decrypt(
+    data: &[u8],
+    wrapped_key: &[u8],
+    private_key_path: &Path,
+    padding: RsaPadding,
+) -> crate::result::Result<Vec<u8>> {
+    let private_key = read_private_key(private_key_path)?;
+    let key = private_key.decrypt(padding.scheme(), wrapped_key)?;
+    // The padding is cut via the data length of the header, zero padding would also strip zeroes
+    // the compressed data ends with
+    let ecb = Ecb::<Aes128, NoPadding>::new_var(key.as_ref(), Default::default())?;
+
+    Ok(ecb.decrypt_vec(data)?)
+}
+
+/// Encrypts the data with a fresh session key, or deterministically with the given one,
+/// returns the encrypted data & the wrapped session key
+pub fn encrypt(
+    data: &[u8],
+    public_key_path: &Path,
+    session_key: Option<&[u8; AES_KEY_SIZE]>,
+    padding: RsaPadding,
+) -> crate::result::Result<(Vec<u8>, Vec<u8>)> {
+    let public_key = read_public_key(public_key_path)?;
+
+    if public_key.size() != WRAPPED_KEY_SIZE {
+        return Err(RustfoilError::UnsupportedKeySize(public_key.size() * 8).into());
+    }
 
-        let encrypted_aes_key = public_key.encrypt(
-            &mut rng,
-            PaddingScheme::new_oaep::<sha2::Sha256>(),
-            random_aes_key.as_ref(),
-        )?;
+    match session_key {
+        Some(key) => Ok((
+            encrypt_aes(data, key)?,
+            wrap_key_deterministic(key, &public_key, padding)?,
+        )),
+        None => {
+            let key = generate_aes_key();
+            Ok((
+                encrypt_aes(data, &key)?,
+                wrap_key(&key, &public_key, padding)?,
+            ))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// Test keypair, the private key only exists for these tests
+    fn testdata(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join(name)
+    }
+
+    /// Two AES blocks, data is padded before it's encrypted
+    const DATA: &[u8; 32] = b"{\"files\":[],\"success\":\"Hello!!\"}";
+
+    #[test]
+    fn ecb_encryption_round_trips() {
+        let (encrypted, wrapped_key) = encrypt(
+            DATA,
+            testdata("test_key.pub.pem").as_path(),
+            None,
+            RsaPadding::Oaep,
+        )
+        .unwrap();
+
+        assert_ne!(&encrypted[..], &DATA[..]);
+        assert_eq!(encrypted.len(), DATA.len());
+
+        let decrypted = decrypt(
+            &encrypted,
+            &wrapped_key,
+            testdata("test_key.pem").as_path(),
+            RsaPadding::Oaep,
+        )
+        .unwrap();
+
+        assert_eq!(&decrypted[..], &DATA[..]);
+    }
+
+    #[test]
+    fn pem_and_der_keys_wrap_to_the_same_length() {
+        let pem = read_public_key(testdata("test_key.pub.pem").as_path()).unwrap();
+        let der = read_public_key(testdata("test_key.pub.der").as_path()).unwrap();
+
+        assert_eq!(pem, der);
+
+        let key = generate_aes_key();
+        let from_pem = wrap_key(&key, &pem, RsaPadding::Oaep).unwrap();
+        let from_der = wrap_key(&key, &der, RsaPadding::Oaep).unwrap();
+
+        assert_eq!(from_pem.len(), from_der.len());
+        assert_eq!(from_der.len(), WRAPPED_KEY_SIZE);
+    }
+
+    #[test]
+    fn unsupported_key_format_is_refused() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+
+        let error = read_public_key(path.as_path()).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            format!(
+                "Public key {} is neither a PEM nor a DER encoded RSA key",
+                path.display()
+            )
+        );
+    }
+
+    #[test]
+    fn session_key_is_aes_128_and_wraps_to_the_modulus_size() {
+        let key = generate_aes_key();
+        let public_key = read_public_key(testdata("test_key.pub.pem").as_path()).unwrap();
+
+        assert_eq!(key.len(), 16);
+        assert_ne!(key, generate_aes_key());
+
+        for padding in [RsaPadding::Oaep, RsaPadding::Pkcs1v15] {
+            assert_eq!(wrap_key(&key, &public_key, padding).unwrap().len(), 256);
+        }
+    }
+
+    #[test]
+    fn keys_other_than_2048_bits_are_refused() {
+        let error = encrypt(
+            DATA,
+            testdata("small_key.pub.pem").as_path(),
+            None,
+            RsaPadding::Oaep,
+        )
+        .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "RSA key has 1024 bits, Tinfoil needs a 2048 bit key"
+        );
+    }
+
+    #[test]
+    fn aes_key_is_parsed_from_hex() {
+        assert_eq!(
+            parse_aes_key(" 000102030405060708090a0b0c0d0e0f ").unwrap(),
+            [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15]
+        );
+        assert!(parse_aes_key("0001").is_err());
+        assert!(parse_aes_key("zz0102030405060708090a0b0c0d0e0f").is_err());
+    }
+
+    #[test]
+    fn decrypted_data_keeps_trailing_zeroes() {
+        let mut data = [0u8; 32];
+        data[..4].copy_from_slice(b"zstd");
+
+        let (encrypted, wrapped_key) = encrypt(
+            &data,
+            testdata("test_key.pub.pem").as_path(),
+            None,
+            RsaPadding::Oaep,
+        )
+        .unwrap();
+        let decrypted = decrypt(
+            &encrypted,
+            &wrapped_key,
+            testdata("test_key.pem").as_path(),
+            RsaPadding::Oaep,
+        )
+        .unwrap();
+
+        assert_eq!(decrypted, data);
+    }
+
+    #[test]
+    fn both_paddings_decrypt_with_the_test_keypair() {
+        let session_key = parse_aes_key("000102030405060708090a0b0c0d0e0f").unwrap();
+
+        for (padding, other) in [
+            (RsaPadding::Oaep, RsaPadding::Pkcs1v15),
+            (RsaPadding::Pkcs1v15, RsaPadding::Oaep),
+        ] {
+            for key in [None, Some(&session_key)] {
+                let (encrypted, wrapped_key) =
+                    encrypt(DATA, testdata("test_key.pub.pem").as_path(), key, padding).unwrap();
+
+                let decrypted = decrypt(
+                    &encrypted,
+                    &wrapped_key,
+                    testdata("test_key.pem").as_path(),
+                    padding,
+                )
+                .unwrap();
+                assert_eq!(&decrypted[..], &DATA[..], "{}", padding);
+
+                // A random wrapping could pass the other padding's checks by chance
+                if key.is_some() {
+                    assert!(decrypt(
+                        &encrypted,
+                        &wrapped_key,
+                        testdata("test_key.pem").as_path(),
+                        other,
+                    )
+                    .is_err());
+                }
+            }
+        }
+    }
 
-        Ok((encrypted_data, encrypted_aes_key))
+    #[test]
+    fn parses_rsa_paddings() {
+        for (value, expected) in [
+            ("OAEP", "oaep"),
+            ("pkcs1v15", "pkcs1v15"),
+            ("pkcs1", "pkcs1v15"),
+        ] {
+            assert_eq!(value.parse::<RsaPadding>().unwrap().to_string(), expected);
+        }
+        assert!("pss".parse::<RsaPadding>().is_err());
     }
 }
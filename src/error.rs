@@ -1,7 +1,459 @@
+use crate::encryption::AES_KEY_SIZE;
+use std::io;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 #[derive(Debug, Error)]
 pub enum RustfoilError {
-    #[error("Credentials file is missing")]
+    #[error("Credentials file is missing, pass it via --credentials or the credentials environment variable")]
     CredentialsMissing,
+    #[error("Credentials file {} of account {name} is missing", .path.display())]
+    AccountCredentialsMissing { name: String, path: PathBuf },
+    #[error("Google authorization failed: {0}")]
+    Auth(String),
+    #[error("Google Drive request timed out after {0} seconds")]
+    Timeout(u64),
+    #[error("Google Drive request failed: {0}")]
+    Drive(String),
+    #[error("Failed to scan folder {folder_id}")]
+    DriveScan {
+        folder_id: String,
+        #[source]
+        source: Box<RustfoilError>,
+    },
+    #[error("Invalid Title ID {0:?}, expected 16 hexadecimal characters")]
+    InvalidTitleId(String),
+    #[error("Invalid size {size:?} of file {name}")]
+    InvalidSize { name: String, size: String },
+    #[error("S3 credentials are missing, set AWS_ACCESS_KEY_ID & AWS_SECRET_ACCESS_KEY")]
+    S3CredentialsMissing,
+    #[error("Uploading {key} to S3 failed with status {status}: {body}")]
+    S3UploadFailed {
+        key: String,
+        status: u16,
+        body: String,
+    },
+    #[error("Failed to share {failed} of {total} files")]
+    ShareFailed { failed: usize, total: usize },
+    #[error("Invalid line {line} of the file list: {reason}")]
+    InvalidFileListLine { line: usize, reason: String },
+    #[error("Invalid CDN fallback URL {0:?}, expected an absolute http(s) URL")]
+    InvalidCdnFallback(String),
+    #[error("Invalid header {0:?}, expected \"Key: Value\" or \"Key=Value\"")]
+    InvalidHeader(String),
+    #[error("Invalid header name {0:?}, expected letters, digits, '-' or '_'")]
+    InvalidHeaderName(String),
+    #[error("Invalid 1Fichier API key {0:?}, expected the key itself rather than a login")]
+    InvalidOneFichierKey(String),
+    #[error("Invalid proxy {proxy:?}: {reason}")]
+    InvalidProxy { proxy: String, reason: String },
+    #[error("Invalid referrer URL {0:?}")]
+    InvalidReferrer(String),
+    #[error("Invalid region tag pattern: {0}")]
+    InvalidRegionTagPattern(String),
+    #[error("Unknown account {0:?}, define it via --account")]
+    UnknownAccount(String),
+    #[error("Invalid Title ID pattern: {0}")]
+    InvalidTitleIdPattern(String),
+    #[error("RSA key has {0} bits, Tinfoil needs a 2048 bit key")]
+    UnsupportedKeySize(usize),
+    #[error("Invalid AES key, expected {} hex characters", AES_KEY_SIZE * 2)]
+    InvalidAesKey,
+    #[error("Deterministic encryption needs a fixed AES key, pass it via --aes-key")]
+    MissingAesKey,
+    #[error("{0}")]
+    ConflictingOptions(String),
+    #[error("Output file {} already exists, pass --overwrite to replace it", .0.display())]
+    OutputExists(PathBuf),
+    #[error("Public key {} is neither a PEM nor a DER encoded RSA key", .0.display())]
+    UnsupportedPublicKey(PathBuf),
+    #[error("Invalid Tinfoil file: {0}")]
+    InvalidTinfoilFile(String),
+    #[error("No files to index, pass --allow-empty to write an empty index anyway")]
+    NoFiles,
+    #[error("Index check found {0} problems")]
+    CheckFailed(usize),
+    #[error("Interrupted")]
+    Cancelled,
+    #[error("Indexes differ in {0} files")]
+    IndexesDiffer(usize),
+    #[error("Couldn't write output file {}", .path.display())]
+    OutputWrite {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Couldn't read {}", .path.display())]
+    InputRead {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Couldn't parse {}", .path.display())]
+    InvalidJsonFile {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+}
+
+impl RustfoilError {
+    /// Wraps an error reading `path`, for use with `map_err`
+    pub fn input_read(path: &Path) -> impl FnOnce(io::Error) -> RustfoilError + '_ {
+        move |source| RustfoilError::InputRead {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    /// Wraps an error parsing the JSON file at `path`, for use with `map_err`
+    pub fn invalid_json_file(path: &Path) -> impl FnOnce(serde_json::Error) -> RustfoilError + '_ {
+        move |source| RustfoilError::InvalidJsonFile {
+            path: path.to_owned(),
+            source,
+        }
+    }
+
+    /// Exit code of the CLI for this error, grouped by category
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            RustfoilError::CredentialsMissing
+            | RustfoilError::AccountCredentialsMissing { .. }
+            | RustfoilError::Auth(_)
+            | RustfoilError::S3CredentialsMissing => EXIT_AUTH,
+            RustfoilError::OutputExists(_)
+            | RustfoilError::OutputWrite { .. }
+            | RustfoilError::InputRead { .. } => EXIT_IO,
+            RustfoilError::InvalidTitleId(_)
+            | RustfoilError::InvalidSize { .. }
+            | RustfoilError::InvalidFileListLine { .. }
+            | RustfoilError::InvalidCdnFallback(_)
+            | RustfoilError::InvalidHeader(_)
+            | RustfoilError::InvalidHeaderName(_)
+            | RustfoilError::InvalidOneFichierKey(_)
+            | RustfoilError::InvalidProxy { .. }
+            | RustfoilError::InvalidReferrer(_)
+            | RustfoilError::InvalidTitleIdPattern(_)
+            | RustfoilError::InvalidRegionTagPattern(_)
+            | RustfoilError::InvalidAesKey
+            | RustfoilError::MissingAesKey
+            | RustfoilError::UnsupportedPublicKey(_)
+            | RustfoilError::UnsupportedKeySize(_)
+            | RustfoilError::InvalidTinfoilFile(_)
+            | RustfoilError::InvalidJsonFile { .. }
+            | RustfoilError::CheckFailed(_)
+            | RustfoilError::UnknownAccount(_)
+            | RustfoilError::ConflictingOptions(_) => EXIT_VALIDATION,
+            RustfoilError::Timeout(_)
+            | RustfoilError::Drive(_)
+            | RustfoilError::ShareFailed { .. }
+            | RustfoilError::S3UploadFailed { .. } => EXIT_REMOTE,
+            RustfoilError::NoFiles => EXIT_EMPTY,
+            RustfoilError::IndexesDiffer(_) => EXIT_DIFFERENCES,
+            RustfoilError::Cancelled => EXIT_INTERRUPTED,
+            RustfoilError::DriveScan { source, .. } => source.exit_code(),
+        }
+    }
+}
+
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_AUTH: i32 = 2;
+pub const EXIT_IO: i32 = 3;
+pub const EXIT_VALIDATION: i32 = 4;
+pub const EXIT_REMOTE: i32 = 5;
+pub const EXIT_EMPTY: i32 = 6;
+pub const EXIT_DIFFERENCES: i32 = 7;
+/// Same code shells report for processes killed by SIGINT
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// Exit code for any error, IO errors without a more specific category use `EXIT_IO`
+pub fn exit_code(error: &anyhow::Error) -> i32 {
+    if let Some(error) = error
+        .chain()
+        .find_map(|e| e.downcast_ref::<RustfoilError>())
+    {
+        return error.exit_code();
+    }
+
+    if error.chain().any(|e| e.is::<std::io::Error>()) {
+        return EXIT_IO;
+    }
+
+    EXIT_FAILURE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_variant_has_an_actionable_message_and_exit_code() {
+        let io_error = || io::Error::new(io::ErrorKind::NotFound, "No such file or directory");
+        let json_error = || serde_json::from_str::<serde_json::Value>("{").unwrap_err();
+
+        let cases = vec![
+            (
+                RustfoilError::CredentialsMissing,
+                "Credentials file is missing, pass it via --credentials or the credentials environment variable",
+                EXIT_AUTH,
+            ),
+            (
+                RustfoilError::AccountCredentialsMissing {
+                    name: "work".to_string(),
+                    path: PathBuf::from("work.json"),
+                },
+                "Credentials file work.json of account work is missing",
+                EXIT_AUTH,
+            ),
+            (
+                RustfoilError::Auth("invalid_grant".to_string()),
+                "Google authorization failed: invalid_grant",
+                EXIT_AUTH,
+            ),
+            (
+                RustfoilError::Timeout(30),
+                "Google Drive request timed out after 30 seconds",
+                EXIT_REMOTE,
+            ),
+            (
+                RustfoilError::Drive("File not found".to_string()),
+                "Google Drive request failed: File not found",
+                EXIT_REMOTE,
+            ),
+            (
+                RustfoilError::DriveScan {
+                    folder_id: "abc".to_string(),
+                    source: Box::new(RustfoilError::Timeout(30)),
+                },
+                "Failed to scan folder abc",
+                EXIT_REMOTE,
+            ),
+            (
+                RustfoilError::InvalidTitleId("0100".to_string()),
+                "Invalid Title ID \"0100\", expected 16 hexadecimal characters",
+                EXIT_VALIDATION,
+            ),
+            (
+                RustfoilError::InvalidSize {
+                    name: "Game.nsp".to_string(),
+                    size: "big".to_string(),
+                },
+                "Invalid size \"big\" of file Game.nsp",
+                EXIT_VALIDATION,
+            ),
+            (
+                RustfoilError::S3CredentialsMissing,
+                "S3 credentials are missing, set AWS_ACCESS_KEY_ID & AWS_SECRET_ACCESS_KEY",
+                EXIT_AUTH,
+            ),
+            (
+                RustfoilError::S3UploadFailed {
+                    key: "index.tfl".to_string(),
+                    status: 403,
+                    body: "AccessDenied".to_string(),
+                },
+                "Uploading index.tfl to S3 failed with status 403: AccessDenied",
+                EXIT_REMOTE,
+            ),
+            (
+                RustfoilError::ShareFailed {
+                    failed: 2,
+                    total: 10,
+                },
+                "Failed to share 2 of 10 files",
+                EXIT_REMOTE,
+            ),
+            (
+                RustfoilError::InvalidFileListLine {
+                    line: 3,
+                    reason: "missing size".to_string(),
+                },
+                "Invalid line 3 of the file list: missing size",
+                EXIT_VALIDATION,
+            ),
+            (
+                RustfoilError::InvalidCdnFallback("cdn".to_string()),
+                "Invalid CDN fallback URL \"cdn\", expected an absolute http(s) URL",
+                EXIT_VALIDATION,
+            ),
+            (
+                RustfoilError::InvalidHeaderName("X Mirror".to_string()),
+                "Invalid header name \"X Mirror\", expected letters, digits, '-' or '_'",
+                EXIT_VALIDATION,
+            ),
+            (
+                RustfoilError::InvalidHeader("Referer".to_string()),
+                "Invalid header \"Referer\", expected \"Key: Value\" or \"Key=Value\"",
+                EXIT_VALIDATION,
+            ),
+            (
+                RustfoilError::InvalidOneFichierKey("me@example.com".to_string()),
+                "Invalid 1Fichier API key \"me@example.com\", expected the key itself rather than a login",
+                EXIT_VALIDATION,
+            ),
+            (
+                RustfoilError::InvalidProxy {
+                    proxy: "proxy".to_string(),
+                    reason: "missing port".to_string(),
+                },
+                "Invalid proxy \"proxy\": missing port",
+                EXIT_VALIDATION,
+            ),
+            (
+                RustfoilError::InvalidReferrer("example".to_string()),
+                "Invalid referrer URL \"example\"",
+                EXIT_VALIDATION,
+            ),
+            (
+                RustfoilError::InvalidRegionTagPattern("unclosed group".to_string()),
+                "Invalid region tag pattern: unclosed group",
+                EXIT_VALIDATION,
+            ),
+            (
+                RustfoilError::UnknownAccount("work".to_string()),
+                "Unknown account \"work\", define it via --account",
+                EXIT_VALIDATION,
+            ),
+            (
+                RustfoilError::InvalidTitleIdPattern("unclosed group".to_string()),
+                "Invalid Title ID pattern: unclosed group",
+                EXIT_VALIDATION,
+            ),
+            (
+                RustfoilError::UnsupportedKeySize(4096),
+                "RSA key has 4096 bits, Tinfoil needs a 2048 bit key",
+                EXIT_VALIDATION,
+            ),
+            (
+                RustfoilError::InvalidAesKey,
+                "Invalid AES key, expected 32 hex characters",
+                EXIT_VALIDATION,
+            ),
+            (
+                RustfoilError::MissingAesKey,
+                "Deterministic encryption needs a fixed AES key, pass it via --aes-key",
+                EXIT_VALIDATION,
+            ),
+            (
+                RustfoilError::ConflictingOptions("--a conflicts with --b".to_string()),
+                "--a conflicts with --b",
+                EXIT_VALIDATION,
+            ),
+            (
+                RustfoilError::OutputExists(PathBuf::from("index.tfl")),
+                "Output file index.tfl already exists, pass --overwrite to replace it",
+                EXIT_IO,
+            ),
+            (
+                RustfoilError::UnsupportedPublicKey(PathBuf::from("public.key")),
+                "Public key public.key is neither a PEM nor a DER encoded RSA key",
+                EXIT_VALIDATION,
+            ),
+            (
+                RustfoilError::InvalidTinfoilFile("bad magic".to_string()),
+                "Invalid Tinfoil file: bad magic",
+                EXIT_VALIDATION,
+            ),
+            (
+                RustfoilError::NoFiles,
+                "No files to index, pass --allow-empty to write an empty index anyway",
+                EXIT_EMPTY,
+            ),
+            (
+                RustfoilError::CheckFailed(2),
+                "Index check found 2 problems",
+                EXIT_VALIDATION,
+            ),
+            (RustfoilError::Cancelled, "Interrupted", EXIT_INTERRUPTED),
+            (
+                RustfoilError::IndexesDiffer(3),
+                "Indexes differ in 3 files",
+                EXIT_DIFFERENCES,
+            ),
+            (
+                RustfoilError::OutputWrite {
+                    path: PathBuf::from("index.tfl"),
+                    source: io_error(),
+                },
+                "Couldn't write output file index.tfl",
+                EXIT_IO,
+            ),
+            (
+                RustfoilError::InputRead {
+                    path: PathBuf::from("token.json"),
+                    source: io_error(),
+                },
+                "Couldn't read token.json",
+                EXIT_IO,
+            ),
+            (
+                RustfoilError::InvalidJsonFile {
+                    path: PathBuf::from("token.json"),
+                    source: json_error(),
+                },
+                "Couldn't parse token.json",
+                EXIT_VALIDATION,
+            ),
+        ];
+
+        for (error, message, code) in cases {
+            assert_eq!(error.to_string(), message);
+            assert_eq!(error.exit_code(), code, "{}", message);
+        }
+    }
+
+    #[test]
+    fn exit_code_falls_back_to_io_and_failure() {
+        let io_error = anyhow::Error::new(io::Error::other("disk full"));
+        let other = anyhow::anyhow!("something else");
+
+        assert_eq!(exit_code(&io_error), EXIT_IO);
+        assert_eq!(exit_code(&other), EXIT_FAILURE);
+        assert_eq!(
+            exit_code(&anyhow::Error::new(RustfoilError::NoFiles).context("Indexing failed")),
+            EXIT_EMPTY
+        );
+    }
+
+    #[test]
+    fn wrapped_errors_print_their_whole_chain() {
+        let scan = anyhow::Error::new(RustfoilError::DriveScan {
+            folder_id: "abc".to_string(),
+            source: Box::new(RustfoilError::Timeout(30)),
+        });
+        assert_eq!(scan.to_string(), "Failed to scan folder abc");
+        assert_eq!(
+            format!("{:#}", scan),
+            "Failed to scan folder abc: Google Drive request timed out after 30 seconds"
+        );
+        assert_eq!(exit_code(&scan), EXIT_REMOTE);
+
+        let path = Path::new("missing-rustfoil-token.json");
+        let read = anyhow::Error::new(
+            std::fs::read(path)
+                .map_err(RustfoilError::input_read(path))
+                .unwrap_err(),
+        )
+        .context("Loading the token failed");
+        assert!(
+            format!("{:#}", read).starts_with(
+                "Loading the token failed: Couldn't read missing-rustfoil-token.json: "
+            ),
+            "{:#}",
+            read
+        );
+        assert_eq!(read.chain().count(), 3);
+        assert_eq!(exit_code(&read), EXIT_IO);
+
+        let parse = anyhow::Error::new(
+            serde_json::from_str::<serde_json::Value>("{")
+                .map_err(RustfoilError::invalid_json_file(Path::new("cache.json")))
+                .unwrap_err(),
+        );
+        assert_eq!(
+            format!("{:#}", parse),
+            "Couldn't parse cache.json: EOF while parsing an object at line 1 column 1"
+        );
+        assert_eq!(exit_code(&parse), EXIT_VALIDATION);
+    }
 }
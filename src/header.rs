@@ -0,0 +1,167 @@
+use crate::error::RustfoilError;
+use reqwest::Url;
+
+/// Normalizes a `Key: Value` or `Key=Value` header into the `Key: Value` form Tinfoil expects
+pub fn normalize_header(header: &str) -> Result<String, RustfoilError> {
+    let invalid = || RustfoilError::InvalidHeader(header.to_string());
+
+    let split = header.find([':', '=']).ok_or_else(invalid)?;
+    let (key, value) = (header[..split].trim(), header[split + 1..].trim());
+
+    if !is_header_name(key) {
+        return Err(invalid());
+    }
+
+    Ok(format!("{}: {}", key, value))
+}
+
+fn is_header_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/// Builds the `<name>: <URL>` header entry for a fallback CDN, the URL has to be an absolute
+/// HTTP(S) URL. Tinfoil only sends the header along with its requests, the server behind the
+/// index has to read `name` & act on it
+pub fn cdn_fallback_header(name: &str, url: &str) -> Result<String, RustfoilError> {
+    let (name, url) = (name.trim(), url.trim());
+
+    if !is_header_name(name) {
+        return Err(RustfoilError::InvalidHeaderName(name.to_string()));
+    }
+
+    match Url::parse(url) {
+        Ok(parsed) if parsed.has_host() && matches!(parsed.scheme(), "http" | "https") => {
+            Ok(format!("{}: {}", name, parsed))
+        }
+        _ => Err(RustfoilError::InvalidCdnFallback(url.to_string())),
+    }
+}
+
+/// Trims a 1Fichier API key and checks it's a single token of URL safe characters,
+/// `user:password` logins aren't accepted by Tinfoil
+pub fn normalize_one_fichier_key(key: &str) -> Result<String, RustfoilError> {
+    let trimmed = key.trim();
+
+    if trimmed.len() < 16
+        || !trimmed
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(RustfoilError::InvalidOneFichierKey(key.to_string()));
+    }
+
+    Ok(trimmed.to_string())
+}
+
+/// Normalizes the referrer into an absolute URL, defaulting to `https://` when no scheme is given
+pub fn normalize_referrer(referrer: &str) -> Result<String, RustfoilError> {
+    let referrer = referrer.trim();
+
+    let url = if referrer.contains("://") {
+        Url::parse(referrer)
+    } else {
+        Url::parse(format!("https://{}", referrer).as_str())
+    };
+
+    match url {
+        Ok(url) if url.has_host() => Ok(url.to_string()),
+        _ => Err(RustfoilError::InvalidReferrer(referrer.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_headers_are_normalized() {
+        let headers: Vec<String> = [
+            "X-Api-Key: secret",
+            "Authorization=Bearer abc",
+            " cookie :a=b",
+        ]
+        .iter()
+        .map(|header| normalize_header(header))
+        .collect::<Result<_, _>>()
+        .unwrap();
+
+        assert_eq!(
+            headers,
+            vec![
+                "X-Api-Key: secret",
+                "Authorization: Bearer abc",
+                "cookie: a=b"
+            ]
+        );
+    }
+
+    #[test]
+    fn invalid_header_list_names_the_offending_entry() {
+        let error = ["X-Api-Key: secret", "no separator", "Bad Key: value"]
+            .iter()
+            .map(|header| normalize_header(header))
+            .collect::<Result<Vec<_>, _>>()
+            .err();
+
+        assert!(
+            matches!(error, Some(RustfoilError::InvalidHeader(header)) if header == "no separator")
+        );
+        assert!(normalize_header("Bad Key: value").is_err());
+        assert!(normalize_header(": value").is_err());
+    }
+
+    #[test]
+    fn referrer_is_normalized_into_an_absolute_url() {
+        assert_eq!(
+            normalize_referrer("example.com").unwrap(),
+            "https://example.com/"
+        );
+        assert_eq!(
+            normalize_referrer(" http://example.com/shop ").unwrap(),
+            "http://example.com/shop"
+        );
+        assert!(normalize_referrer("").is_err());
+        assert!(normalize_referrer("https://").is_err());
+    }
+
+    #[test]
+    fn one_fichier_key_is_trimmed_and_logins_are_refused() {
+        assert_eq!(
+            normalize_one_fichier_key(" AbCdEf0123456789_-xyz \n").unwrap(),
+            "AbCdEf0123456789_-xyz"
+        );
+        assert!(matches!(
+            normalize_one_fichier_key("user@example.com:hunter22"),
+            Err(RustfoilError::InvalidOneFichierKey(key)) if key == "user@example.com:hunter22"
+        ));
+        assert!(normalize_one_fichier_key("short").is_err());
+    }
+
+    #[test]
+    fn cdn_fallback_header_is_a_well_formed_entry() {
+        let header = cdn_fallback_header(" X-Mirror ", " https://cdn.example.com/switch ").unwrap();
+
+        assert_eq!(header, "X-Mirror: https://cdn.example.com/switch");
+        assert_eq!(normalize_header(&header).unwrap(), header);
+        assert_eq!(
+            cdn_fallback_header("Fallback_Cdn", "http://cdn.example.com").unwrap(),
+            "Fallback_Cdn: http://cdn.example.com/"
+        );
+
+        for url in ["cdn.example.com", "ftp://cdn.example.com", "https://", ""] {
+            assert!(matches!(
+                cdn_fallback_header("X-Mirror", url),
+                Err(RustfoilError::InvalidCdnFallback(invalid)) if invalid == url
+            ));
+        }
+        for name in ["", "X Mirror", "X-Mirror:"] {
+            assert!(matches!(
+                cdn_fallback_header(name, "https://cdn.example.com"),
+                Err(RustfoilError::InvalidHeaderName(invalid)) if invalid == name
+            ));
+        }
+    }
+}
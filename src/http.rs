@@ -0,0 +1,149 @@
+use crate::gdrive::FileInfo;
+use crate::result::Result;
+use percent_encoding::percent_decode_str;
+use regex::Regex;
+use reqwest::blocking::Client;
+use reqwest::header::{CONTENT_LENGTH, CONTENT_TYPE};
+use reqwest::Url;
+use serde::Deserialize;
+use std::time::Duration;
+
+pub struct HttpService {
+    client: Client,
+    link_regex: Regex,
+}
+
+/// Entry of a JSON manifest, either a plain URL or an object with optional size & name
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ManifestEntry {
+    Url(String),
+    File {
+        url: String,
+        size: Option<u64>,
+        name: Option<String>,
+    },
+}
+
+impl HttpService {
+    pub fn new(timeout: Duration) -> Result<HttpService> {
+        Ok(HttpService {
+            client: Client::builder()
+                .connect_timeout(timeout)
+                .timeout(timeout)
+                .build()?,
+            link_regex: Regex::new("href=\"([^\"]+)\"")?,
+        })
+    }
+
+    /// Lists all files of a directory listing or JSON manifest, the `id` of each `FileInfo` holds its absolute URL
+    pub fn get_all_files(&self, url: &str, recursion: bool) -> Result<Vec<FileInfo>> {
+        let resp = self.client.get(url).send()?.error_for_status()?;
+
+        // Use the final URL so redirects (e.g. to add a trailing slash) resolve links correctly
+        let base = resp.url().clone();
+
+        let is_json = base.path().ends_with(".json")
+            || resp
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .is_some_and(|value| value.contains("json"));
+
+        let body = resp.text()?;
+
+        if is_json {
+            self.parse_manifest(&base, body.as_str())
+        } else {
+            self.parse_listing(&base, body.as_str(), recursion)
+        }
+    }
+
+    fn parse_manifest(&self, base: &Url, body: &str) -> Result<Vec<FileInfo>> {
+        let entries: Vec<ManifestEntry> = serde_json::from_str(body)?;
+        let mut files = Vec::new();
+
+        for entry in entries {
+            let (url, size, name) = match entry {
+                ManifestEntry::Url(url) => (url, None, None),
+                ManifestEntry::File { url, size, name } => (url, size, name),
+            };
+
+            let url = base.join(url.as_str())?;
+            let name = name.unwrap_or_else(|| HttpService::file_name(&url));
+
+            let size = match size {
+                Some(size) => Some(size),
+                None => self.content_length(&url)?,
+            };
+
+            if let Some(size) = size {
+                files.push(FileInfo::new(url.to_string(), size.to_string(), name, true));
+            }
+        }
+
+        Ok(files)
+    }
+
+    fn parse_listing(&self, base: &Url, body: &str, recursion: bool) -> Result<Vec<FileInfo>> {
+        let mut files = Vec::new();
+
+        for capture in self.link_regex.captures_iter(body) {
+            let link = &capture[1];
+
+            // Skip parent directory & column sorting links
+            if link.starts_with('?') || link.starts_with('#') || link.starts_with("..") {
+                continue;
+            }
+
+            let url = base.join(link)?;
+
+            // Only follow links pointing below the listed directory
+            if url.origin() != base.origin() || !url.path().starts_with(base.path()) {
+                continue;
+            }
+
+            if url.path() == base.path() {
+                continue;
+            }
+
+            if url.path().ends_with('/') {
+                if recursion {
+                    files.append(&mut self.get_all_files(url.as_str(), recursion)?);
+                }
+                continue;
+            }
+
+            if let Some(size) = self.content_length(&url)? {
+                files.push(FileInfo::new(
+                    url.to_string(),
+                    size.to_string(),
+                    HttpService::file_name(&url),
+                    true,
+                ));
+            }
+        }
+
+        Ok(files)
+    }
+
+    fn content_length(&self, url: &Url) -> Result<Option<u64>> {
+        let resp = self.client.head(url.clone()).send()?.error_for_status()?;
+
+        Ok(resp
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.parse::<u64>().ok()))
+    }
+
+    /// Percent-decoded last path segment of the URL
+    pub fn file_name(url: &Url) -> String {
+        let segment = url
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .unwrap_or("");
+
+        percent_decode_str(segment).decode_utf8_lossy().to_string()
+    }
+}
@@ -1,10 +1,106 @@
 use crate::gdrive::FileInfo;
-use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use crate::name::{self, NameParts};
+use core::fmt;
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 use serde_with::skip_serializing_none;
+use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
+
+/// Characters escaped in file names, only those unsafe inside a URL fragment (plus `#` & `%`),
+/// so names like `Game [0100000000010000].nsp` stay readable. Non-ASCII is always escaped
+const NAME_ENCODE_SET: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'%')
+    .add(b'<')
+    .add(b'>')
+    .add(b'`');
+
+/// Key naming of the written index. `V1` keeps the field names as they always were written,
+/// `V2` uses the camelCase keys newer Tinfoil versions document, e.g. `googleApiKey`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IndexSchema {
+    V1,
+    V2,
+}
+
+/// Keys renamed by `IndexSchema::V2`
+const V2_KEYS: [(&str, &str); 7] = [
+    ("google_api_key", "googleApiKey"),
+    ("one_fichier_keys", "oneFichierKeys"),
+    ("client_cert_pub", "clientCertPub"),
+    ("client_cert_key", "clientCertKey"),
+    ("theme_blacklist", "themeBlackList"),
+    ("theme_whitelist", "themeWhiteList"),
+    ("theme_error", "themeError"),
+];
+
+impl FromStr for IndexSchema {
+    type Err = String;
+
+    fn from_str(schema: &str) -> std::result::Result<Self, Self::Err> {
+        match schema.to_lowercase().as_ref() {
+            "v1" => Ok(IndexSchema::V1),
+            "v2" => Ok(IndexSchema::V2),
+            _ => Err(format!(
+                "Invalid schema: {}, valid choices are v1 & v2",
+                schema
+            )),
+        }
+    }
+}
+
+impl fmt::Display for IndexSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IndexSchema::V1 => write!(f, "v1"),
+            IndexSchema::V2 => write!(f, "v2"),
+        }
+    }
+}
+
+/// What makes two scanned files duplicates, of which only the first is indexed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupeStrategy {
+    /// Same Title ID, files without one are always kept
+    TitleId,
+    /// Exactly the same file name
+    Name,
+    /// Same URL, or the same Drive file listed in several folders
+    Url,
+}
+
+impl FromStr for DedupeStrategy {
+    type Err = String;
+
+    fn from_str(strategy: &str) -> std::result::Result<Self, Self::Err> {
+        match strategy.to_lowercase().as_ref() {
+            "title-id" => Ok(DedupeStrategy::TitleId),
+            "name" => Ok(DedupeStrategy::Name),
+            "url" => Ok(DedupeStrategy::Url),
+            _ => Err(format!(
+                "Invalid dedupe strategy: {}, valid choices are title-id, name & url",
+                strategy
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DedupeStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DedupeStrategy::TitleId => write!(f, "title-id"),
+            DedupeStrategy::Name => write!(f, "name"),
+            DedupeStrategy::Url => write!(f, "url"),
+        }
+    }
+}
 
 #[skip_serializing_none]
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Index {
     pub files: Option<Vec<FileEntry>>,
     pub directories: Option<Vec<String>>,
@@ -26,15 +122,117 @@ pub struct Index {
     pub theme_whitelist: Option<Vec<String>>,
     #[serde(rename(deserialize = "themeError"))]
     pub theme_error: Option<String>,
+    /// RFC 3339 timestamp of the generation, Tinfoil ignores it
+    #[serde(rename = "generatedAt")]
+    pub generated_at: Option<String>,
+    /// Name & version of the tool which generated the index, Tinfoil ignores it
+    pub generator: Option<String>,
 }
 
-#[derive(Serialize, Deserialize)]
+/// A single file of the index. Tinfoil reads exactly one `url` per entry, there is no field for
+/// mirrors, so a file available from several sources has to be picked from one of them
+#[derive(Serialize, Deserialize, Clone)]
 pub struct FileEntry {
     url: String,
     size: u64,
 }
 
+impl Default for Index {
+    fn default() -> Self {
+        Index::new()
+    }
+}
+
 impl Index {
+    /// Appends the directories which aren't listed yet, keeping their order
+    pub fn add_directories(&mut self, directories: impl IntoIterator<Item = String>) {
+        let listed = self.directories.get_or_insert_with(Vec::new);
+
+        for directory in directories {
+            if !listed.contains(&directory) {
+                listed.push(directory);
+            }
+        }
+    }
+
+    /// Serializes the index with the key naming of `schema`
+    pub fn to_json(&self, schema: IndexSchema) -> serde_json::Result<String> {
+        if schema == IndexSchema::V1 {
+            return serde_json::to_string(self);
+        }
+
+        let mut value = serde_json::to_value(self)?;
+
+        if let Some(object) = value.as_object_mut() {
+            for (v1, v2) in &V2_KEYS {
+                if let Some(field) = object.remove(*v1) {
+                    object.insert(v2.to_string(), field);
+                }
+            }
+        }
+
+        serde_json::to_string(&value)
+    }
+
+    /// Files added, removed or changed in size in `new` compared to this index
+    pub fn diff(&self, new: &Index) -> IndexDiff {
+        let old_files: HashMap<&str, &FileEntry> = self
+            .files
+            .iter()
+            .flatten()
+            .map(|file| (file.url(), file))
+            .collect();
+        let new_urls: HashSet<&str> = new.files.iter().flatten().map(|file| file.url()).collect();
+
+        let mut diff = IndexDiff::default();
+
+        for file in new.files.iter().flatten() {
+            match old_files.get(file.url()) {
+                None => diff.added.push(file.to_owned()),
+                Some(old) if old.size != file.size => {
+                    diff.changed.push(((*old).to_owned(), file.to_owned()))
+                }
+                Some(_) => {}
+            }
+        }
+
+        diff.removed = self
+            .files
+            .iter()
+            .flatten()
+            .filter(|file| !new_urls.contains(file.url()))
+            .cloned()
+            .collect();
+
+        diff
+    }
+
+    /// Problems which would make Tinfoil fail to load files of the index, empty if it's fine
+    pub fn problems(&self) -> Vec<String> {
+        let mut problems = Vec::new();
+        let mut urls = HashSet::new();
+
+        for (i, file) in self.files.iter().flatten().enumerate() {
+            if file.url.trim().is_empty() {
+                problems.push(format!("File {} has an empty URL", i + 1));
+            } else if !urls.insert(file.url.as_str()) {
+                problems.push(format!("File {} has the duplicate URL {}", i + 1, file.url));
+            }
+
+            if file.size == 0 {
+                problems.push(format!("File {} ({}) has a size of 0", i + 1, file.name()));
+            }
+        }
+
+        if let Some(version) = self.version {
+            if !version.is_finite() || version < 0.0 {
+                problems.push(format!("Minimum version {} isn't a valid version", version));
+            }
+        }
+
+        problems
+    }
+
     pub fn new() -> Index {
         Index {
             files: None,
@@ -50,7 +248,66 @@ impl Index {
             theme_blacklist: None,
             theme_whitelist: None,
             theme_error: None,
+            generated_at: None,
+            generator: None,
+        }
+    }
+
+    /// Splits the files into multiple indexes whose serialized JSON stays below `max_size` bytes,
+    /// every part keeps all remaining settings. A single file larger than `max_size` gets its own part
+    pub fn split(&self, max_size: u64) -> serde_json::Result<Vec<Index>> {
+        let mut base = self.clone();
+        base.files = Some(Vec::new());
+
+        let base_size = serde_json::to_string(&base)?.len() as u64;
+
+        let mut parts: Vec<Vec<FileEntry>> = Vec::new();
+        let mut current: Vec<FileEntry> = Vec::new();
+        let mut current_size = base_size;
+
+        for entry in self.files.iter().flatten() {
+            // Entry plus separating comma
+            let entry_size = serde_json::to_string(entry)?.len() as u64 + 1;
+
+            if !current.is_empty() && current_size + entry_size > max_size {
+                parts.push(std::mem::take(&mut current));
+                current_size = base_size;
+            }
+
+            current.push(entry.to_owned());
+            current_size += entry_size;
+        }
+
+        if !current.is_empty() || parts.is_empty() {
+            parts.push(current);
         }
+
+        Ok(parts
+            .into_iter()
+            .map(|files| Index {
+                files: Some(files),
+                ..base.clone()
+            })
+            .collect())
+    }
+}
+
+/// Files which differ between two indexes, matched by URL
+#[derive(Default)]
+pub struct IndexDiff {
+    pub added: Vec<FileEntry>,
+    pub removed: Vec<FileEntry>,
+    /// Old & new entry of files whose size changed
+    pub changed: Vec<(FileEntry, FileEntry)>,
+}
+
+impl IndexDiff {
+    pub fn len(&self) -> usize {
+        self.added.len() + self.removed.len() + self.changed.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 }
 
@@ -58,26 +315,340 @@ impl FileEntry {
     pub fn new(url: String, size: u64) -> FileEntry {
         FileEntry { url, size }
     }
+
+    pub fn url(&self) -> &str {
+        self.url.as_str()
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// File name shown in Tinfoil, decoded from the URL fragment
+    pub fn name(&self) -> String {
+        let fragment = self.url.rsplit_once('#').map_or("", |(_, name)| name);
+
+        percent_decode_str(fragment).decode_utf8_lossy().to_string()
+    }
+}
+
+/// Sum of the sizes of all files, files with an unparseable size are skipped
+pub fn total_size(files: &[ParsedFileInfo]) -> u64 {
+    files
+        .iter()
+        .filter_map(|file| file.size.parse::<u64>().ok())
+        .sum()
 }
 
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 pub struct ParsedFileInfo {
     pub id: String,
     pub size: String,
     pub name: String,
     pub name_encoded: String,
     pub shared: bool,
+    pub url: Option<String>,
+    /// RFC 3339 timestamp of the last modification, if known
+    pub modified_time: Option<String>,
+    /// Name of the account the file was scanned with, `None` for the default account
+    pub account: Option<String>,
 }
 
 impl ParsedFileInfo {
-    pub fn new(info: FileInfo) -> ParsedFileInfo {
-        let name_encoded = utf8_percent_encode(info.name.as_str(), NON_ALPHANUMERIC).to_string();
+    /// Builds the encoded name from `name_template` if given, falling back to the original name
+    /// when the template can't be satisfied. `include_path` prefixes it with the file's folder path,
+    /// the `version_property` of the file is preferred over the version in its name
+    pub fn new(
+        info: FileInfo,
+        name_template: Option<&str>,
+        include_path: bool,
+        version_property: Option<&str>,
+    ) -> ParsedFileInfo {
+        let mut display_name = name_template
+            .and_then(|template| {
+                let mut parts = NameParts::parse(info.name.as_str());
+
+                if let Some(version) = version_property.and_then(|key| info.properties.get(key)) {
+                    parts.version = Some(version.to_owned());
+                }
+
+                parts.render(template)
+            })
+            .unwrap_or_else(|| info.name.to_owned());
+
+        if let (true, Some(path)) = (include_path, &info.path) {
+            display_name = format!("{}/{}", path, display_name);
+        }
+
+        let name_encoded = utf8_percent_encode(display_name.as_str(), NAME_ENCODE_SET).to_string();
         ParsedFileInfo {
             id: info.id,
             size: info.size,
             name: info.name,
             name_encoded,
             shared: info.shared,
+            url: None,
+            modified_time: info.modified_time,
+            account: None,
         }
     }
+
+    /// Lowercase file extension without the leading dot, empty if the name has none
+    pub fn extension(&self) -> String {
+        match self.name.rfind('.') {
+            Some(pos) => self.name[pos + 1..].to_lowercase(),
+            None => String::new(),
+        }
+    }
+
+    /// Removes every match of `tags` from the encoded name, the file itself keeps its name
+    pub fn strip_tags(&mut self, tags: &Regex) {
+        let name = percent_decode_str(self.name_encoded.as_str()).decode_utf8_lossy();
+
+        self.name_encoded =
+            utf8_percent_encode(name::strip_tags(&name, tags).as_str(), NAME_ENCODE_SET)
+                .to_string();
+    }
+
+    /// Lowercases the extension of the encoded name, the file itself keeps its name
+    pub fn lowercase_extension(&mut self) {
+        let extension = self.extension();
+        let start = match self.name_encoded.len().checked_sub(extension.len() + 1) {
+            Some(start) if !extension.is_empty() => start,
+            _ => return,
+        };
+
+        let matches = self.name_encoded.get(start..).is_some_and(|suffix| {
+            suffix[1..].eq_ignore_ascii_case(extension.as_str()) && suffix.starts_with('.')
+        });
+
+        if matches {
+            self.name_encoded.truncate(start + 1);
+            self.name_encoded.push_str(extension.as_str());
+        }
+    }
+
+    /// Creates a file which is linked by its plain URL (stored in `info.id`) instead of its gdrive ID
+    pub fn new_http(info: FileInfo, name_template: Option<&str>) -> ParsedFileInfo {
+        ParsedFileInfo {
+            url: Some(info.id.to_owned()),
+            ..ParsedFileInfo::new(info, name_template, false, None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(urls: &[&str]) -> Vec<String> {
+        urls.iter().map(|url| url.to_string()).collect()
+    }
+
+    #[test]
+    fn directories_combine_without_duplicates() {
+        let mut index = Index::new();
+
+        index.add_directories(urls(&[
+            "https://example.com/a.tfl",
+            "https://example.com/b.tfl",
+            "https://example.com/a.tfl",
+        ]));
+        index.add_directories(urls(&[
+            "https://example.com/b.tfl",
+            "https://example.com/index_1.tfl",
+        ]));
+
+        assert_eq!(
+            index.directories,
+            Some(urls(&[
+                "https://example.com/a.tfl",
+                "https://example.com/b.tfl",
+                "https://example.com/index_1.tfl",
+            ]))
+        );
+    }
+
+    #[test]
+    fn entry_serializes_as_the_single_url_shape_tinfoil_reads() {
+        let entry = FileEntry::new("gdrive:1a#Game%20[0100000000010000].nsp".to_string(), 5);
+
+        assert_eq!(
+            serde_json::to_string(&entry).unwrap(),
+            r#"{"url":"gdrive:1a#Game%20[0100000000010000].nsp","size":5}"#
+        );
+        assert_eq!(entry.name(), "Game [0100000000010000].nsp");
+    }
+
+    fn encoded(name: &str) -> String {
+        ParsedFileInfo::new(
+            FileInfo::new("id".to_string(), "1".to_string(), name.to_string(), false),
+            None,
+            false,
+            None,
+        )
+        .name_encoded
+    }
+
+    #[test]
+    fn spaces_are_encoded_and_brackets_kept() {
+        assert_eq!(
+            encoded("Game [0100000000010000].nsp"),
+            "Game%20[0100000000010000].nsp"
+        );
+    }
+
+    #[test]
+    fn realistic_name_only_encodes_unsafe_characters() {
+        assert_eq!(
+            encoded("Pokémon - Let's Go, Eevee! [0100187003A36000][v0] (US) #1 100%.nsp"),
+            "Pok%C3%A9mon%20-%20Let's%20Go,%20Eevee!%20[0100187003A36000][v0]%20(US)%20%231%20100%25.nsp"
+        );
+    }
+
+    #[test]
+    fn schema_selects_the_key_naming() {
+        let index = Index {
+            files: Some(vec![FileEntry::new("gdrive:1a#Game.nsp".to_string(), 5)]),
+            success: Some("Hello".to_string()),
+            google_api_key: Some("key".to_string()),
+            one_fichier_keys: Some(vec!["1f".to_string()]),
+            theme_blacklist: Some(vec!["theme".to_string()]),
+            theme_error: Some("No themes".to_string()),
+            ..Index::new()
+        };
+        let keys = |schema: IndexSchema| {
+            let value: serde_json::Value =
+                serde_json::from_str(&index.to_json(schema).unwrap()).unwrap();
+            let mut keys: Vec<String> = value.as_object().unwrap().keys().cloned().collect();
+            keys.sort();
+            keys
+        };
+
+        assert_eq!(
+            keys(IndexSchema::V1),
+            vec![
+                "files",
+                "google_api_key",
+                "one_fichier_keys",
+                "success",
+                "theme_blacklist",
+                "theme_error"
+            ]
+        );
+        assert_eq!(
+            keys(IndexSchema::V2),
+            vec![
+                "files",
+                "googleApiKey",
+                "oneFichierKeys",
+                "success",
+                "themeBlackList",
+                "themeError"
+            ]
+        );
+
+        let read: Index = serde_json::from_str(&index.to_json(IndexSchema::V2).unwrap()).unwrap();
+        assert_eq!(read.google_api_key.as_deref(), Some("key"));
+        assert_eq!(read.theme_error.as_deref(), Some("No themes"));
+    }
+
+    #[test]
+    fn parses_schemas() {
+        assert_eq!("V2".parse::<IndexSchema>(), Ok(IndexSchema::V2));
+        assert_eq!(IndexSchema::V1.to_string(), "v1");
+        assert!("v3".parse::<IndexSchema>().is_err());
+    }
+
+    #[test]
+    fn lowercases_only_the_extension() {
+        let lowercased = |name: &str| {
+            let mut file = ParsedFileInfo::new(
+                FileInfo::new("1a".to_string(), "5".to_string(), name.to_string(), false),
+                None,
+                false,
+                None,
+            );
+            file.lowercase_extension();
+            (file.name, file.name_encoded)
+        };
+
+        assert_eq!(
+            lowercased("GAME.NSP"),
+            ("GAME.NSP".to_string(), "GAME.nsp".to_string())
+        );
+        assert_eq!(
+            lowercased("Game [v0].Nsz"),
+            ("Game [v0].Nsz".to_string(), "Game%20[v0].nsz".to_string())
+        );
+        assert_eq!(lowercased("README").1, "README");
+    }
+
+    #[test]
+    fn diff_classifies_added_removed_and_changed_files() {
+        let index = |files: &[(&str, u64)]| Index {
+            files: Some(
+                files
+                    .iter()
+                    .map(|(url, size)| FileEntry::new(url.to_string(), *size))
+                    .collect(),
+            ),
+            ..Index::new()
+        };
+        let old = index(&[
+            ("gdrive:1a#A.nsp", 1),
+            ("gdrive:2b#B.nsp", 2),
+            ("gdrive:3c#C.nsp", 3),
+        ]);
+        let new = index(&[
+            ("gdrive:2b#B.nsp", 2),
+            ("gdrive:3c#C.nsp", 30),
+            ("gdrive:4d#D.nsp", 4),
+        ]);
+
+        let diff = old.diff(&new);
+        let urls = |files: &[FileEntry]| {
+            files
+                .iter()
+                .map(|file| file.url().to_string())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(urls(&diff.added), vec!["gdrive:4d#D.nsp"]);
+        assert_eq!(urls(&diff.removed), vec!["gdrive:1a#A.nsp"]);
+        assert_eq!(diff.changed.len(), 1);
+        assert_eq!(diff.changed[0].0.size(), 3);
+        assert_eq!(diff.changed[0].1.size(), 30);
+        assert_eq!(diff.len(), 3);
+
+        assert!(new.diff(&new).is_empty());
+        assert_eq!(Index::new().diff(&old).added.len(), 3);
+    }
+
+    #[test]
+    fn total_size_skips_unparseable_sizes() {
+        let file = |size: &str| {
+            ParsedFileInfo::new(
+                FileInfo::new(
+                    "1a".to_string(),
+                    size.to_string(),
+                    "Game [0100000000010000][v0].nsp".to_string(),
+                    false,
+                ),
+                None,
+                false,
+                None,
+            )
+        };
+        let files = vec![
+            file("1073741824"),
+            file("unknown"),
+            file(""),
+            file("536870912"),
+        ];
+
+        assert_eq!(total_size(&files), 1610612736);
+        assert_eq!(crate::size::format_size(total_size(&files)), "1.50 GiB");
+        assert_eq!(total_size(&[]), 0);
+    }
 }
@@ -0,0 +1,87 @@
+//! Generates Tinfoil index files from Google Drive folders & HTTP listings
+
+use crate::gdrive::FolderEstimate;
+use crate::index::{IndexDiff, ParsedFileInfo};
+use crate::progress::ProgressSink;
+use std::path::PathBuf;
+use std::time::Duration;
+
+mod cache;
+pub mod cancel;
+pub mod compression;
+mod config;
+pub mod encryption;
+pub mod error;
+pub mod gdrive;
+pub mod header;
+mod http;
+pub mod index;
+pub mod logging;
+pub mod name;
+pub mod progress;
+pub mod result;
+mod service;
+pub mod size;
+pub mod storage;
+pub mod tinfoil;
+mod token;
+
+pub use config::{Account, RustfoilConfig};
+pub use service::RustfoilService;
+
+/// Outcome of a successful run
+#[derive(Debug)]
+pub struct Report {
+    /// Files which were added to the index
+    pub files: Vec<ParsedFileInfo>,
+    /// Summed size of `files` in bytes
+    pub total_size: u64,
+    /// Paths of all written index files
+    pub index_paths: Vec<PathBuf>,
+    /// Google Drive IDs or other storage identifiers of the uploaded index files
+    pub uploaded: Vec<String>,
+    pub elapsed: Duration,
+}
+
+/// Generates, writes and optionally shares & uploads the index described by `config`
+pub fn run(config: RustfoilConfig) -> result::Result<Report> {
+    RustfoilService::new(config).run()
+}
+
+/// Same as `run`, reporting the progress of scanning, sharing & writing to `progress`
+pub fn run_with_progress(
+    config: RustfoilConfig,
+    progress: Box<dyn ProgressSink>,
+) -> result::Result<Report> {
+    RustfoilService::new(config).with_progress(progress).run()
+}
+
+/// Checks the index file at `config.check` for problems, failing if any are found
+pub fn check_index(config: RustfoilConfig) -> result::Result<()> {
+    RustfoilService::new(config).check_index()
+}
+
+/// Compares the two index files at `config.diff`, returns the files which differ
+pub fn diff_indexes(config: RustfoilConfig) -> result::Result<IndexDiff> {
+    RustfoilService::new(config).diff_indexes()
+}
+
+/// Authorizes with Google Drive, returns the email address of the authorized account
+pub fn verify_credentials(config: RustfoilConfig) -> result::Result<String> {
+    RustfoilService::new(config).verify_credentials()
+}
+
+/// Counts the entries on the first page of every folder, a quick check before a full scan
+pub fn estimate(config: RustfoilConfig) -> result::Result<Vec<FolderEstimate>> {
+    RustfoilService::new(config).estimate()
+}
+
+/// Logs size & time of every compression on the index at `config.benchmark_compression`
+pub fn benchmark_compression(config: RustfoilConfig) -> result::Result<()> {
+    RustfoilService::new(config).benchmark_compression()
+}
+
+/// Trains a zstd dictionary from `config.dict_samples`, returns the path it was written to
+pub fn train_dictionary(config: RustfoilConfig) -> result::Result<PathBuf> {
+    RustfoilService::new(config).train_dictionary()
+}
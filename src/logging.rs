@@ -1,4 +1,6 @@
-use console::{style, Color, Term};
+use console::{style, Color, Term, TermTarget};
+use core::fmt;
+use std::str::FromStr;
 
 #[derive(Debug, PartialEq, Clone, Copy)]
 pub enum LogLevel {
@@ -9,14 +11,104 @@ pub enum LogLevel {
     Error = 4,
 }
 
+/// How log lines are written, `Json` writes one object with level, timestamp & message per line
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(format: &str) -> std::result::Result<Self, Self::Err> {
+        match format.to_lowercase().as_ref() {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "Invalid log format: {}, valid choices are text & json",
+                format
+            )),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Whether output is colored, `Auto` colors terminals unless `NO_COLOR` is set
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ColorChoice {
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    /// Whether to color output written to a terminal or not, `no_color` tells if `NO_COLOR` is set
+    pub fn enabled(self, is_terminal: bool, no_color: bool) -> bool {
+        match self {
+            ColorChoice::Auto => is_terminal && !no_color,
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+
+    /// Applies the choice to the logger & progress bars, for stdout & stderr separately
+    pub fn apply(self) {
+        let no_color = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+
+        console::set_colors_enabled(
+            self.enabled(Term::stdout().features().is_attended(), no_color),
+        );
+        console::set_colors_enabled_stderr(
+            self.enabled(Term::stderr().features().is_attended(), no_color),
+        );
+    }
+}
+
+impl FromStr for ColorChoice {
+    type Err = String;
+
+    fn from_str(choice: &str) -> std::result::Result<Self, Self::Err> {
+        match choice.to_lowercase().as_ref() {
+            "auto" => Ok(ColorChoice::Auto),
+            "always" => Ok(ColorChoice::Always),
+            "never" => Ok(ColorChoice::Never),
+            _ => Err(format!(
+                "Invalid color choice: {}, valid choices are auto, always & never",
+                choice
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ColorChoice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ColorChoice::Auto => write!(f, "auto"),
+            ColorChoice::Always => write!(f, "always"),
+            ColorChoice::Never => write!(f, "never"),
+        }
+    }
+}
+
+#[derive(Clone)]
 pub struct Logger {
     pub level: LogLevel,
+    pub format: LogFormat,
     pub out: Term,
     pub err: Term,
 }
 
 impl LogLevel {
-    pub fn to_string(&self) -> &'static str {
+    pub fn to_string(self) -> &'static str {
         match self {
             LogLevel::Trace => "Trace",
             LogLevel::Debug => "Debug",
@@ -31,10 +123,23 @@ impl Logger {
     pub fn new(level: LogLevel) -> Logger {
         Logger {
             level,
+            format: LogFormat::Text,
             out: Term::stdout(),
             err: Term::stderr(),
         }
     }
+
+    pub fn with_format(mut self, format: LogFormat) -> Logger {
+        self.format = format;
+        self
+    }
+
+    /// Writes all levels to stderr, keeping stdout free for output
+    pub fn to_stderr(mut self) -> Logger {
+        self.out = Term::stderr();
+        self
+    }
+
     pub fn log_trace(&self, message: &str) -> std::io::Result<()> {
         self.log(message, LogLevel::Trace)
     }
@@ -69,9 +174,19 @@ impl Logger {
             std = &self.err;
         }
 
-        std.write_line(&*format!(
+        if self.format == LogFormat::Json {
+            return std.write_line(&Logger::json_line(message, level));
+        }
+
+        let mut level_style = style(level.to_string());
+
+        if std.target() == TermTarget::Stderr {
+            level_style = level_style.for_stderr();
+        }
+
+        std.write_line(&format!(
             "[{}] {}",
-            style(level.to_string()).fg(match level {
+            level_style.fg(match level {
                 LogLevel::Trace => Color::White,
                 LogLevel::Debug => Color::Blue,
                 LogLevel::Info => Color::Green,
@@ -81,4 +196,57 @@ impl Logger {
             message
         ))
     }
+
+    /// One JSON object with the level, an RFC 3339 timestamp & the message
+    fn json_line(message: &str, level: LogLevel) -> String {
+        serde_json::json!({
+            "level": level.to_string().to_lowercase(),
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "message": message,
+        })
+        .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_line_per_message() {
+        let line = Logger::json_line("Wrote \"index.tfl\"\nto disk", LogLevel::Warning);
+        assert!(!line.contains('\n'));
+
+        let value: serde_json::Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["level"], "warning");
+        assert_eq!(value["message"], "Wrote \"index.tfl\"\nto disk");
+        assert!(chrono::DateTime::parse_from_rfc3339(value["timestamp"].as_str().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn parses_log_formats() {
+        assert_eq!("JSON".parse::<LogFormat>(), Ok(LogFormat::Json));
+        assert_eq!("text".parse::<LogFormat>(), Ok(LogFormat::Text));
+        assert!("xml".parse::<LogFormat>().is_err());
+    }
+
+    #[test]
+    fn color_decision_per_choice() {
+        // (is_terminal, no_color)
+        let cases = [(true, false), (true, true), (false, false), (false, true)];
+
+        let enabled = |choice: ColorChoice| {
+            cases
+                .iter()
+                .map(|(is_terminal, no_color)| choice.enabled(*is_terminal, *no_color))
+                .collect::<Vec<bool>>()
+        };
+
+        assert_eq!(enabled(ColorChoice::Auto), vec![true, false, false, false]);
+        assert_eq!(enabled(ColorChoice::Always), vec![true; 4]);
+        assert_eq!(enabled(ColorChoice::Never), vec![false; 4]);
+
+        assert_eq!("ALWAYS".parse::<ColorChoice>(), Ok(ColorChoice::Always));
+        assert!("sometimes".parse::<ColorChoice>().is_err());
+    }
 }
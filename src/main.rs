@@ -1,41 +1,33 @@
-#[cfg_attr(test, macro_use)]
-extern crate structopt;
-
-use crate::logging::LogLevel::{Debug, Info, Trace};
-use anyhow::Error;
-use compression::CompressionFlag;
-use encryption::EncryptionFlag;
-use error::RustfoilError;
-use gdrive::GDriveService;
-use hhmmss::Hhmmss;
-use index::FileEntry;
-use index::Index;
-use index::ParsedFileInfo;
-use indicatif::{ProgressBar, ProgressStyle};
-use logging::Logger;
-use regex::Regex;
-use std::borrow::Borrow;
+use crate::progress_bars::ProgressBars;
+use chrono::{DateTime, NaiveDate, Utc};
+use rustfoil::compression::CompressionFlag;
+use rustfoil::encryption::RsaPadding;
+use rustfoil::error::{self, RustfoilError};
+use rustfoil::index::{DedupeStrategy, IndexSchema};
+use rustfoil::logging::{ColorChoice, LogFormat, LogLevel, Logger};
+use rustfoil::name::TitleIdStyle;
+use rustfoil::progress::{NoProgress, ProgressSink};
+use rustfoil::{size, Account, RustfoilConfig};
 use std::path::PathBuf;
-use std::time::Instant;
 use structopt::StructOpt;
-use tinfoil::convert_to_tinfoil_format;
 
-mod compression;
-mod encryption;
-mod error;
-mod gdrive;
-mod index;
-mod logging;
-mod result;
-mod tinfoil;
+mod progress_bars;
 
 /// Script that will allow you to generate an index file with Google Drive file links for use with Tinfoil
 #[derive(StructOpt, Debug)]
 #[structopt(rename_all = "kebab-case")]
 pub struct Input {
-    /// Folder IDs of Google Drive folders to scan
+    /// Folder IDs or share URLs of Google Drive folders to scan
     folder_ids: Vec<String>,
 
+    /// URL of a HTTP directory listing or JSON manifest to scan
+    #[structopt(long)]
+    http_url: Option<String>,
+
+    /// Reads the files from stdin instead of scanning, one "URL<TAB>size[<TAB>name]" entry per line
+    #[structopt(long)]
+    from_stdin: bool,
+
     /// Path to Google Application Credentials
     #[structopt(long, parse(from_os_str), default_value = "credentials.json")]
     credentials: PathBuf,
@@ -44,30 +36,234 @@ pub struct Input {
     #[structopt(long, parse(from_os_str), default_value = "token.json")]
     token: PathBuf,
 
-    /// Path to output index file
+    /// Environment variable containing the Google Application Credentials JSON, preferred over --credentials when set
+    #[structopt(long, default_value = "RUSTFOIL_CREDENTIALS")]
+    credentials_env: String,
+
+    /// Environment variable containing the Google OAuth2.0 User Token JSON, preferred over --token when set. Refreshed tokens are not persisted
+    #[structopt(long, default_value = "RUSTFOIL_TOKEN")]
+    token_env: String,
+
+    /// Additional Google account as name=credentials.json,token.json, scans the folders mapped to it via --folder-account
+    #[structopt(long, number_of_values = 1, parse(try_from_str = parse_account))]
+    account: Vec<Account>,
+
+    /// Scans a folder with another account as folder_id=account_name, other folders use --credentials & --token
+    #[structopt(long, number_of_values = 1, parse(try_from_str = parse_folder_account))]
+    folder_account: Vec<(String, String)>,
+
+    /// Path to output index file, - writes it to stdout
     #[structopt(short = "o", long, parse(from_os_str), default_value = "index.tfl")]
     output_path: PathBuf,
 
+    /// Directory to write the index file to, named after --name-pattern
+    #[structopt(long, parse(from_os_str), conflicts_with = "output-path")]
+    output_dir: Option<PathBuf>,
+
+    /// File name of the index inside --output-dir, {date}, {count} & {size} are replaced like in --success
+    #[structopt(long, default_value = "index.tfl")]
+    name_pattern: String,
+
+    /// Overwrites existing index files instead of refusing to write them
+    #[structopt(long)]
+    overwrite: bool,
+
     /// Share all files inside the index file
     #[structopt(long)]
     share_files: bool,
 
+    /// Number of files shared in parallel
+    #[structopt(long, default_value = "8")]
+    share_concurrency: usize,
+
+    /// Caps the Google Drive API requests in flight across scanning, sharing & uploading. It also caps --share-concurrency, extra share workers wait for a free slot
+    #[structopt(long)]
+    max_concurrent_requests: Option<usize>,
+
+    /// Shares files again even when they're already shared, e.g. after their permissions got revoked
+    #[structopt(long)]
+    reshare: bool,
+
+    /// Path to a cache of file IDs shared by earlier runs, which then aren't shared again
+    #[structopt(long, parse(from_os_str))]
+    share_cache: Option<PathBuf>,
+
+    /// Forgets the file IDs in the share cache, the cache is still updated with this run's shares
+    #[structopt(long)]
+    refresh_share_cache: bool,
+
     /// Scans for files only in top directory for each Folder ID entered
     #[structopt(long)]
     no_recursion: bool,
 
+    /// Prefix used for Google Drive file URLs in the index, followed by the file ID
+    #[structopt(long, default_value = "gdrive:")]
+    url_prefix: String,
+
+    /// Prefix used instead of --url-prefix for Google Drive files with this extension, e.g. nsz=myproxy:, can be repeated
+    #[structopt(long, number_of_values = 1, parse(try_from_str = parse_url_map))]
+    url_map: Vec<(String, String)>,
+
+    /// Path to a cache of folder listings, reused as long as the folder wasn't modified. Only used with --no-recursion & without --share-files, as changes in subfolders & shares don't change a folder's modification time
+    #[structopt(long, parse(from_os_str))]
+    cache: Option<PathBuf>,
+
+    /// Maximum age in seconds of cached folder listings
+    #[structopt(long, default_value = "86400")]
+    cache_ttl: u64,
+
+    /// Ignores cached folder listings, the cache is still updated with the fresh listings
+    #[structopt(long)]
+    no_cache: bool,
+
+    /// Logs and skips folders which fail to scan instead of aborting
+    #[structopt(long)]
+    continue_on_error: bool,
+
     /// Adds files without valid Title ID
     #[structopt(long)]
     add_nsw_files_without_title_id: bool,
 
+    /// Regex used to find the Title ID in file names instead of [0100...], the first group has to capture the ID. It is matched against the plain, not percent-encoded, name
+    #[structopt(long)]
+    title_id_pattern: Option<String>,
+
+    /// Naming scheme Title IDs are found by, bracket ([0100...]), brace ({0100...}), bare16 (16 hex digits on their own) or none, which keeps files without one. Defaults to bracket
+    #[structopt(long, conflicts_with = "title-id-pattern")]
+    title_id_style: Option<TitleIdStyle>,
+
     /// Adds files without valid NSW ROM extension(NSP/NSZ/XCI/XCZ) to index
     #[structopt(long)]
     add_non_nsw_files: bool,
 
-    /// Adds a success message to index file to show if index is successfully read by Tinfoil
+    /// Indexes everything the scan returns, skipping the shortcut, Google-native, owner, extension, Title ID, size, date & list filters. Handy to check rustfoil sees the files before debugging why they are dropped
+    #[structopt(long)]
+    no_filter: bool,
+
+    /// Adds Google-native files like Docs or Sheets, which are skipped by default as they can't be downloaded
+    #[structopt(long)]
+    include_google_native: bool,
+
+    /// Includes the files Drive shortcuts point to, shortcuts are skipped otherwise
+    #[structopt(long)]
+    follow_shortcuts: bool,
+
+    /// Also indexes files in the trash
+    #[structopt(long)]
+    include_trashed: bool,
+
+    /// Logs the trashed files which would have been indexed, for cleaning up the trash
+    #[structopt(long)]
+    report_trashed: bool,
+
+    /// Skips files not owned by the authenticated user, as they usually can't be shared
+    #[structopt(long)]
+    owner_only: bool,
+
+    /// Only adds the first N scanned files to the index, handy for quick previews
+    #[structopt(long)]
+    max_files: Option<usize>,
+
+    /// Writes the index even if no files were found, instead of failing
+    #[structopt(long)]
+    allow_empty: bool,
+
+    /// Comma separated list of file extensions to add to the index, NRO files are never required to have a Title ID
+    #[structopt(long, use_delimiter = true, default_value = "nsp,nsz,xci,xcz,nro")]
+    extensions: Vec<String>,
+
+    /// Only adds NSZ files, replaces --extensions
+    #[structopt(long, conflicts_with_all = &["xci-only", "installed-only", "extensions"])]
+    nsz_only: bool,
+
+    /// Only adds XCI & XCZ files, replaces --extensions
+    #[structopt(long, conflicts_with_all = &["installed-only", "extensions"])]
+    xci_only: bool,
+
+    /// Only adds installable NSP & NSZ files, replaces --extensions
+    #[structopt(long, conflicts_with = "extensions")]
+    installed_only: bool,
+
+    /// Skips files smaller than this size, e.g. 100MB
+    #[structopt(long, parse(try_from_str = size::parse_size))]
+    min_size: Option<u64>,
+
+    /// Skips files larger than this size, e.g. 8GiB
+    #[structopt(long, parse(try_from_str = size::parse_size))]
+    max_size: Option<u64>,
+
+    /// Caps the summed size of the indexed files, e.g. 2TB. The newest files by modification time are kept, older ones are dropped once the budget is used up
+    #[structopt(long, parse(try_from_str = size::parse_size))]
+    max_total_size: Option<u64>,
+
+    /// Only adds files modified at or after this date, either RFC 3339 or YYYY-MM-DD (UTC)
+    #[structopt(long, parse(try_from_str = parse_since))]
+    since: Option<DateTime<Utc>>,
+
+    /// Keeps files without a known modification date when using --since, like files from HTTP listings
+    #[structopt(long)]
+    include_unknown_mtime: bool,
+
+    /// Aborts instead of skipping files whose size Google Drive reports as missing or invalid
+    #[structopt(long)]
+    strict_size: bool,
+
+    /// Warns about Title IDs which appear in more than one scanned file
+    #[structopt(long)]
+    warn_duplicates: bool,
+
+    /// Removes files duplicating an earlier one by title-id, name or url, keeping the first. Files without a Title ID are kept by title-id
+    #[structopt(long)]
+    dedupe_strategy: Option<DedupeStrategy>,
+
+    /// Path to a file of Title IDs (one per line), only files with one of these Title IDs are added to the index
+    #[structopt(long, parse(from_os_str))]
+    title_id_allowlist: Option<PathBuf>,
+
+    /// Path to a file of Title IDs (one per line), files with one of these Title IDs are left out even if allowlisted
+    #[structopt(long, parse(from_os_str))]
+    title_id_denylist: Option<PathBuf>,
+
+    /// Template for the file names shown in Tinfoil, supports {title}, {title_id} & {version} parsed from the file name. The extension is appended automatically, files not matching the template keep their name
+    #[structopt(long)]
+    name_template: Option<String>,
+
+    /// Logs the original & encoded name of every indexed file at trace level (-vv)
+    #[structopt(long)]
+    debug_names: bool,
+
+    /// Drive file property (custom or app property) holding the version used for {version} in --name-template, takes precedence over the version parsed from the file name which stays the fallback
+    #[structopt(long)]
+    version_from_metadata: Option<String>,
+
+    /// Prefixes the file names shown in Tinfoil with their folder path relative to the scanned folder, e.g. Base/Game [TitleID].nsp
+    #[structopt(long)]
+    include_path: bool,
+
+    /// Lowercases file extensions in the index (GAME.NSP becomes GAME.nsp), the files on Drive keep their names
+    #[structopt(long)]
+    normalize_extensions: bool,
+
+    /// Removes region & language tags like (USA) or (En,Fr) from the names in the index, the files on Drive keep their names
+    #[structopt(long)]
+    strip_region_tags: bool,
+
+    /// Regex of the tags removed by --strip-region-tags, replaces the default region & language tags
+    #[structopt(long)]
+    region_tag_regex: Option<String>,
+
+    /// Shows only the file names without their folder path, the default
+    #[structopt(long, conflicts_with = "include-path")]
+    flatten: bool,
+
+    /// Adds a success message to index file to show if index is successfully read by Tinfoil, {count}, {size} & {date} are replaced with the number & total size of the files and the current date
     #[structopt(long)]
     success: Option<String>,
 
+    /// Records the generation time & rustfoil version in the index (generatedAt & generator), which Tinfoil ignores
+    #[structopt(long)]
+    embed_metadata: bool,
+
     /// Adds a referrer to index file to prevent others from hotlinking
     #[structopt(long)]
     referrer: Option<String>,
@@ -80,10 +276,26 @@ pub struct Input {
     #[structopt(long)]
     one_fichier_keys: Option<Vec<String>>,
 
-    /// Adds custom HTTP headers Tinfoil should send with its requests
+    /// Aborts instead of warning when a 1Fichier key looks malformed
+    #[structopt(long)]
+    strict_keys: bool,
+
+    /// Adds custom HTTP headers Tinfoil should send with its requests, each in "Key: Value" or "Key=Value" form
     #[structopt(long)]
     headers: Option<Vec<String>>,
 
+    /// Adds a fallback CDN URL to the headers, sent in the header named by --cdn-fallback-header. Tinfoil only forwards it with its requests, your server has to act on it
+    #[structopt(long, requires = "cdn-fallback-header")]
+    cdn_fallback: Option<String>,
+
+    /// Name of the header --cdn-fallback is sent in, whichever one your server reads
+    #[structopt(long, requires = "cdn-fallback")]
+    cdn_fallback_header: Option<String>,
+
+    /// Adds URLs of other indexes Tinfoil should load as well, can be repeated
+    #[structopt(long = "directory", number_of_values = 1)]
+    directories: Option<Vec<String>>,
+
     /// Adds a minimum Tinfoil version to load the index
     #[structopt(long)]
     min_version: Option<f64>,
@@ -96,14 +308,26 @@ pub struct Input {
     #[structopt(long)]
     theme_whitelist: Option<Vec<String>>,
 
-    /// Adds a custom theme error message to the index
+    /// Adds a custom theme error message to the index, supports the same placeholders as --success
     #[structopt(long)]
     theme_error: Option<String>,
 
-    /// Path to RSA Public Key to encrypt AES-ECB-256 key with
+    /// Path to RSA Public Key to encrypt the AES-128-ECB key with, the only mode Tinfoil supports
     #[structopt(long)]
     public_key: Option<PathBuf>,
 
+    /// Padding of the RSA encrypted session key, oaep (what Tinfoil expects) or pkcs1v15
+    #[structopt(long, default_value = "oaep")]
+    rsa_padding: RsaPadding,
+
+    /// Fixed AES-128 session key as 32 hex characters instead of a random one, needed by --deterministic when encrypting
+    #[structopt(long)]
+    aes_key: Option<String>,
+
+    /// Produces byte-identical output for the same files by sorting them by URL, requires --aes-key when encrypting. Avoid {date} in messages for this
+    #[structopt(long)]
+    deterministic: bool,
+
     /// Shares the index file that is uploaded to Google Drive
     #[structopt(long)]
     share_index: bool,
@@ -116,328 +340,550 @@ pub struct Input {
     #[structopt(long)]
     upload_my_drive: bool,
 
-    /// Which compression should be used for the index file
-    #[structopt(long, default_value = "zstd")]
-    compression: CompressionFlag,
+    /// Continues an interrupted upload of the index from the last byte Drive received instead of starting over
+    #[structopt(long)]
+    resume_upload: bool,
 
-    /// If OAuth should be done headless
+    /// Uploads the index files to this S3-compatible bucket, using the AWS_ACCESS_KEY_ID & AWS_SECRET_ACCESS_KEY environment variables
     #[structopt(long)]
-    headless: bool,
+    s3_bucket: Option<String>,
 
-    /// Verbose mode (-v, -vv, -vvv, etc.)
-    #[structopt(short, long, parse(from_occurrences))]
-    verbose: u8,
-}
+    /// Object key of the index in the S3 bucket, split parts are stored next to it. Defaults to the file name
+    #[structopt(long)]
+    s3_key: Option<String>,
 
-pub struct RustfoilService {
-    logger: Logger,
-    input: Input,
-    gdrive: Option<GDriveService>,
-    timer: Instant,
-}
+    /// Region of the S3 bucket
+    #[structopt(long, default_value = "us-east-1")]
+    s3_region: String,
 
-impl RustfoilService {
-    pub fn new(input: Input) -> RustfoilService {
-        RustfoilService {
-            logger: Logger::new(match input.verbose {
-                1 => Debug,
-                2 => Trace,
-                _ => Info,
-            }),
-            timer: Instant::now(),
-            gdrive: None,
-            input,
-        }
-    }
+    /// Endpoint of an S3-compatible service, defaults to AWS for the region
+    #[structopt(long)]
+    s3_endpoint: Option<String>,
 
-    pub fn init(&mut self) {
-        self.gdrive = Some(GDriveService::new(
-            self.input.credentials.as_path(),
-            self.input.token.as_path(),
-            self.input.headless,
-        ));
-    }
+    /// Splits the index into multiple files (index_1.tfl, index_2.tfl, ...) whose uncompressed JSON stays below this size, e.g. 5MB
+    #[structopt(long, parse(try_from_str = size::parse_size))]
+    split_size: Option<u64>,
 
-    pub fn validate_input(&self) -> result::Result<()> {
-        if !&self.input.credentials.exists() {
-            return Err(Error::new(RustfoilError::CredentialsMissing));
-        }
+    /// Writes one index per folder instead of a combined one, named after the folder ID (index_<ID>.tfl, ...)
+    #[structopt(long)]
+    per_folder: bool,
 
-        Ok(())
-    }
+    /// Base URL the split parts are reachable at, writes a parent index to the output path which references all parts via directories
+    #[structopt(long)]
+    split_base_url: Option<String>,
 
-    pub fn generate_index(&self, files: Vec<ParsedFileInfo>) -> result::Result<Box<Index>> {
-        let mut index = Box::new(Index::new());
+    /// Additionally writes a gzip compressed copy of the index file next to it
+    #[structopt(long)]
+    gzip_sidecar: bool,
 
-        let mut index_files: Vec<FileEntry> = Vec::new();
+    /// Additionally writes the SHA-256 of the index file next to it as <output>.sha256, verifiable with sha256sum -c
+    #[structopt(long)]
+    checksum_sidecar: bool,
 
-        for info in files {
-            index_files.push(FileEntry::new(
-                format!("gdrive:{}#{}", info.id, info.name_encoded),
-                u64::from_str_radix(&*info.size, 10)?,
-            ));
-        }
+    /// Warns when the index has more files than this, as some Tinfoil builds struggle with large indexes
+    #[structopt(long, default_value = "50000")]
+    warn_threshold: usize,
 
-        index.files = Some(index_files);
+    /// Writes a plain text table of the name, size & Title ID of every indexed file to this path
+    #[structopt(long, parse(from_os_str))]
+    report: Option<PathBuf>,
 
-        self.logger.log_debug("Added files to index")?;
+    /// Writes the URL of every indexed file to this path, one per line
+    #[structopt(long, parse(from_os_str))]
+    urls_only: Option<PathBuf>,
 
-        if let Some(success) = &self.input.success {
-            index.success = Some(
-                success
-                    .to_string()
-                    .replace("\\n", "\n")
-                    .replace("\\t", "\t"),
-            );
-            self.logger.log_debug("Added success message to index")?;
-        }
+    /// Skips writing the index file, e.g. when only --urls-only is needed
+    #[structopt(long)]
+    no_index: bool,
 
-        if let Some(referrer) = &self.input.referrer {
-            index.referrer = Some(referrer.to_string());
-            self.logger.log_debug("Added referrer to index")?;
-        }
+    /// Which compression should be used for the index file: off, zstd, zlib or auto to pick the smallest
+    #[structopt(long, default_value = "zstd")]
+    compression: CompressionFlag,
 
-        if let Some(keys) = &self.input.google_api_key {
-            index.google_api_key = Some(keys.to_string());
-            self.logger.log_debug("Added google api key to index")?;
-        }
+    /// Key naming of the index, v1 as always written or v2 for the camelCase keys of newer Tinfoil versions
+    #[structopt(long, default_value = "v1")]
+    schema: IndexSchema,
 
-        if let Some(keys) = &self.input.one_fichier_keys {
-            index.one_fichier_keys = Some(keys.to_owned());
-            self.logger.log_debug("Added 1Fichier keys to index")?;
-        }
+    /// Number of threads used for zstd compression, 0 uses one per CPU
+    #[structopt(long, default_value = "1")]
+    compression_threads: u32,
 
-        if let Some(headers) = &self.input.headers {
-            index.headers = Some(headers.to_owned());
-            self.logger.log_debug("Added headers to index")?;
-        }
+    /// Path to a zstd dictionary used when compressing with zstd, the index can then only be read by clients which have the same dictionary
+    #[structopt(long, parse(from_os_str))]
+    zstd_dict: Option<PathBuf>,
 
-        if let Some(version) = &self.input.min_version {
-            index.version = Some(version.to_owned());
-            self.logger.log_debug("Added minimum version to index")?;
-        }
+    /// Trains a zstd dictionary from the --dict-samples index JSON files, writes it to this path & exits
+    #[structopt(long, parse(from_os_str))]
+    train_dict: Option<PathBuf>,
 
-        if let Some(theme) = &self.input.theme_blacklist {
-            index.theme_blacklist = Some(theme.to_owned());
-            self.logger.log_debug("Added theme blacklist to index")?;
-        }
+    /// Uncompressed index JSON files to train the zstd dictionary from
+    #[structopt(long, parse(from_os_str))]
+    dict_samples: Vec<PathBuf>,
 
-        if let Some(theme) = &self.input.theme_whitelist {
-            index.theme_whitelist = Some(theme.to_owned());
-            self.logger.log_debug("Added theme whitelist to index")?;
-        }
+    /// Checks an existing index file for problems instead of generating one, exits non-zero if any are found
+    #[structopt(long, parse(from_os_str))]
+    check: Option<PathBuf>,
 
-        if let Some(error) = &self.input.theme_error {
-            index.theme_error = Some(error.to_string());
-            self.logger
-                .log_debug("Added theme error message to index")?;
-        }
+    /// Prints every file of the index as a JSON object per line to stdout instead of writing the index, e.g. for jq
+    #[structopt(long)]
+    list: bool,
 
-        self.logger.log_info("Generated index successfully")?;
+    /// Compares two index files (OLD NEW) instead of generating one, listing added, removed & resized files, exits with 7 if they differ
+    #[structopt(long, number_of_values = 2, value_names = &["OLD", "NEW"], parse(from_os_str))]
+    diff: Vec<PathBuf>,
 
-        Ok(index)
-    }
+    /// Path to the RSA Private Key used to decrypt encrypted index files for --check & --diff
+    #[structopt(long, parse(from_os_str))]
+    private_key: Option<PathBuf>,
 
-    pub fn output_index(&self, index: Index) -> result::Result<()> {
-        let json = serde_json::to_string(&index)?;
-        let compression = self.input.compression.to_owned();
-        let encryption = match self.input.public_key {
-            None => EncryptionFlag::NoEncrypt,
-            Some(_) => EncryptionFlag::Encrypt,
-        };
+    /// Compares size & time of every compression at a few levels on this index (JSON or .tfl) instead of generating one
+    #[structopt(long, parse(from_os_str))]
+    benchmark_compression: Option<PathBuf>,
 
-        std::fs::write(
-            &self.input.output_path,
-            convert_to_tinfoil_format(
-                json.as_str(),
-                compression,
-                encryption,
-                self.input.public_key.to_owned(),
-            )?,
-        )
-        .expect("Couldn't write output file to Path");
-
-        self.logger.log_info(
-            format!(
-                "Finished writing {} to disk, using {} compression & {}encryption",
-                &self
-                    .input
-                    .output_path
-                    .file_name()
-                    .unwrap()
-                    .to_str()
-                    .unwrap(),
-                compression = match compression {
-                    CompressionFlag::Off => "no".to_string(),
-                    CompressionFlag::ZSTD | CompressionFlag::Zlib => {
-                        compression.to_string()
-                    }
-                },
-                encryptiom = match encryption {
-                    EncryptionFlag::NoEncrypt => "no ",
-                    EncryptionFlag::Encrypt => "",
-                }
-            )
-            .as_str(),
-        )?;
-
-        Ok(())
-    }
+    /// Authorizes with Google Drive & logs the account's email address instead of generating an index
+    #[structopt(long)]
+    verify_credentials: bool,
 
-    pub fn share_file(&self, file_id: String, is_shared: &bool) {
-        if !is_shared {
-            self.gdrive.as_ref().unwrap().share_file(file_id.as_str());
-        }
-    }
+    /// Quickly estimates the number of files in the folders from a single request per folder instead of generating an index
+    #[structopt(long)]
+    estimate: bool,
 
-    pub fn share_files(&self, files: Vec<ParsedFileInfo>) -> result::Result<()> {
-        let pb = ProgressBar::new(files.len() as u64);
+    /// If OAuth should be done headless
+    #[structopt(long)]
+    headless: bool,
 
-        pb.set_style(
-            ProgressStyle::default_bar()
-                .template("[{elapsed_precise}] [{bar:40.cyan/blue}] {msg} {pos:>7}/{len:7} Files")
-                .progress_chars("#>-"),
-        );
+    /// Authorize via a device code which can be entered on any other device, only works for OAuth clients of type "TVs and Limited Input devices"
+    #[structopt(long)]
+    auth_device_code: bool,
 
-        pb.set_message("Sharing");
+    /// Seconds to wait for the device code to be authorized
+    #[structopt(long, default_value = "300")]
+    auth_timeout: u64,
 
-        for file in files {
-            self.share_file(file.id, &file.shared);
-            pb.inc(1);
-        }
+    /// Timeout in seconds for connecting to & reading from Google Drive
+    #[structopt(long, default_value = "30")]
+    timeout: u64,
 
-        pb.finish_with_message("Finished Sharing");
+    /// HTTP proxy Google Drive requests are sent through, e.g. http://proxy:3128. Overrides HTTPS_PROXY & HTTP_PROXY, which are used otherwise unless NO_PROXY excludes Google
+    #[structopt(long)]
+    proxy: Option<String>,
 
-        Ok(())
-    }
+    /// Format of log lines, text or json. json writes one object per line and disables the progress bars
+    #[structopt(long, default_value = "text")]
+    log_format: LogFormat,
 
-    pub fn upload_index(&self) -> std::io::Result<(String, bool)> {
-        let folder_id = &self.input.upload_folder_id;
-        let input = self.input.output_path.as_path();
+    /// When to color the output, auto, always or never. auto colors terminals unless NO_COLOR is set
+    #[structopt(long, default_value = "auto")]
+    color: ColorChoice,
 
-        let res = self
-            .gdrive
-            .as_ref()
-            .unwrap()
-            .upload_file(input, &self.input.upload_folder_id)
-            .unwrap();
+    /// Verbose mode (-v, -vv, -vvv, etc.)
+    #[structopt(short, long, parse(from_occurrences))]
+    verbose: u8,
+}
+
+impl Input {
+    /// Extensions of the chosen preset, --extensions if none was chosen
+    fn extensions(&self) -> Vec<String> {
+        let preset: &[&str] = if self.nsz_only {
+            &["nsz"]
+        } else if self.xci_only {
+            &["xci", "xcz"]
+        } else if self.installed_only {
+            &["nsp", "nsz"]
+        } else {
+            return self.extensions.to_owned();
+        };
+
+        preset
+            .iter()
+            .map(|extension| extension.to_string())
+            .collect()
+    }
+}
 
-        self.logger.log_info(
-            format!(
-                "Uploaded Index to {}",
-                destination = folder_id
-                    .as_ref()
-                    .unwrap_or("My Drive".to_string().borrow())
-            )
-            .as_str(),
-        )?;
-
-        Ok(res)
+impl From<Input> for RustfoilConfig {
+    fn from(input: Input) -> Self {
+        let extensions = input.extensions();
+
+        RustfoilConfig {
+            folder_ids: input.folder_ids,
+            http_url: input.http_url,
+            from_stdin: input.from_stdin,
+            credentials: input.credentials,
+            token: input.token,
+            credentials_env: input.credentials_env,
+            token_env: input.token_env,
+            accounts: input.account,
+            folder_accounts: input.folder_account,
+            output_path: input.output_path,
+            output_dir: input.output_dir,
+            name_pattern: input.name_pattern,
+            overwrite: input.overwrite,
+            share_files: input.share_files,
+            share_concurrency: input.share_concurrency,
+            max_concurrent_requests: input.max_concurrent_requests,
+            reshare: input.reshare,
+            share_cache: input.share_cache,
+            refresh_share_cache: input.refresh_share_cache,
+            no_recursion: input.no_recursion,
+            url_prefix: input.url_prefix,
+            url_map: input.url_map,
+            cache: input.cache,
+            cache_ttl: input.cache_ttl,
+            no_cache: input.no_cache,
+            continue_on_error: input.continue_on_error,
+            add_nsw_files_without_title_id: input.add_nsw_files_without_title_id,
+            title_id_pattern: input.title_id_pattern,
+            title_id_style: input.title_id_style.unwrap_or(TitleIdStyle::Bracket),
+            add_non_nsw_files: input.add_non_nsw_files,
+            no_filter: input.no_filter,
+            include_google_native: input.include_google_native,
+            follow_shortcuts: input.follow_shortcuts,
+            include_trashed: input.include_trashed,
+            report_trashed: input.report_trashed,
+            owner_only: input.owner_only,
+            max_files: input.max_files,
+            allow_empty: input.allow_empty,
+            extensions,
+            min_size: input.min_size,
+            max_size: input.max_size,
+            max_total_size: input.max_total_size,
+            since: input.since,
+            include_unknown_mtime: input.include_unknown_mtime,
+            strict_size: input.strict_size,
+            warn_duplicates: input.warn_duplicates,
+            dedupe_strategy: input.dedupe_strategy,
+            title_id_allowlist: input.title_id_allowlist,
+            title_id_denylist: input.title_id_denylist,
+            name_template: input.name_template,
+            debug_names: input.debug_names,
+            version_from_metadata: input.version_from_metadata,
+            include_path: input.include_path && !input.flatten,
+            normalize_extensions: input.normalize_extensions,
+            strip_region_tags: input.strip_region_tags,
+            region_tag_regex: input.region_tag_regex,
+            success: input.success,
+            embed_metadata: input.embed_metadata,
+            referrer: input.referrer,
+            google_api_key: input.google_api_key,
+            one_fichier_keys: input.one_fichier_keys,
+            strict_keys: input.strict_keys,
+            headers: input.headers,
+            cdn_fallback: input.cdn_fallback_header.zip(input.cdn_fallback),
+            directories: input.directories,
+            min_version: input.min_version,
+            theme_blacklist: input.theme_blacklist,
+            theme_whitelist: input.theme_whitelist,
+            theme_error: input.theme_error,
+            public_key: input.public_key,
+            rsa_padding: input.rsa_padding,
+            aes_key: input.aes_key,
+            deterministic: input.deterministic,
+            share_index: input.share_index,
+            upload_folder_id: input.upload_folder_id,
+            upload_my_drive: input.upload_my_drive,
+            resume_upload: input.resume_upload,
+            s3_bucket: input.s3_bucket,
+            s3_key: input.s3_key,
+            s3_region: input.s3_region,
+            s3_endpoint: input.s3_endpoint,
+            split_size: input.split_size,
+            per_folder: input.per_folder,
+            split_base_url: input.split_base_url,
+            gzip_sidecar: input.gzip_sidecar,
+            checksum_sidecar: input.checksum_sidecar,
+            warn_threshold: input.warn_threshold,
+            report: input.report,
+            urls_only: input.urls_only,
+            no_index: input.no_index,
+            compression: input.compression,
+            schema: input.schema,
+            compression_threads: input.compression_threads,
+            zstd_dict: input.zstd_dict,
+            train_dict: input.train_dict,
+            dict_samples: input.dict_samples,
+            check: input.check,
+            list: input.list,
+            diff: match input.diff.as_slice() {
+                [old, new] => Some((old.to_owned(), new.to_owned())),
+                _ => None,
+            },
+            private_key: input.private_key,
+            benchmark_compression: input.benchmark_compression,
+            verify_credentials: input.verify_credentials,
+            estimate: input.estimate,
+            headless: input.headless,
+            auth_device_code: input.auth_device_code,
+            auth_timeout: input.auth_timeout,
+            timeout: input.timeout,
+            proxy: input.proxy,
+            log_format: input.log_format,
+            log_level: match input.verbose {
+                1 => LogLevel::Debug,
+                2 => LogLevel::Trace,
+                _ => LogLevel::Info,
+            },
+        }
     }
+}
 
-    pub fn share_index(&self, file_id: String, is_shared: bool) -> std::io::Result<()> {
-        self.share_file(file_id, &is_shared);
-        self.logger.log_info("Shared Index File")
+/// Parses an RFC 3339 timestamp or a plain date, which is taken as midnight UTC
+fn parse_since(since: &str) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(since) {
+        return Ok(time.with_timezone(&Utc));
     }
 
-    pub fn scan_folder(&mut self) -> result::Result<Vec<ParsedFileInfo>> {
-        let re = Regex::new("%5B[0-9A-Fa-f]{16}%5D")?;
+    NaiveDate::parse_from_str(since, "%Y-%m-%d")
+        .map(|date| DateTime::from_utc(date.and_hms(0, 0, 0), Utc))
+        .map_err(|_| format!("Invalid date: {}, expected RFC 3339 or YYYY-MM-DD", since))
+}
 
-        // Trigger Authentication if needed
-        self.gdrive.as_ref().unwrap().trigger_auth();
+/// Parses an `ext=prefix` URL mapping, a leading dot of the extension is optional
+fn parse_url_map(mapping: &str) -> Result<(String, String), String> {
+    match mapping.split_once('=') {
+        Some((extension, prefix)) if !extension.trim().is_empty() && !prefix.is_empty() => Ok((
+            extension.trim().trim_start_matches('.').to_lowercase(),
+            prefix.to_string(),
+        )),
+        _ => Err(format!(
+            "Invalid URL mapping: {}, expected <extension>=<prefix>",
+            mapping
+        )),
+    }
+}
 
-        let pb = ProgressBar::new(!0);
-        pb.enable_steady_tick(130);
-        pb.set_style(
-            ProgressStyle::default_spinner()
-                // For more spinners check out the cli-spinners project:
-                // https://github.com/sindresorhus/cli-spinners/blob/master/spinners.json
-                .tick_strings(&["-", "\\", "|", "/"])
-                .template("{spinner:.blue} {msg}"),
-        );
-        pb.set_message("Scanning...");
+fn parse_account(account: &str) -> Result<Account, String> {
+    let invalid = || {
+        format!(
+            "Invalid account: {}, expected <name>=<credentials>,<token>",
+            account
+        )
+    };
 
-        let files: Vec<ParsedFileInfo> = self
-            .input
-            .folder_ids
-            .iter()
-            .map(|id| -> Vec<ParsedFileInfo> {
-                self.gdrive
-                    .as_ref()
-                    .unwrap()
-                    .get_all_files_in_folder(id.to_owned().as_str(), !self.input.no_recursion)
-                    .unwrap()
-                    .into_iter()
-                    .map(|file_info| ParsedFileInfo::new(file_info))
-                    .filter(|file| {
-                        let mut keep = true;
-
-                        if !self.input.add_non_nsw_files {
-                            let extension: String = file
-                                .name
-                                .chars()
-                                .skip(file.name.len() - 4)
-                                .take(4)
-                                .collect();
-
-                            keep = vec![".nsp", ".nsz", ".xci", ".xcz"].contains(&&*extension);
-                        }
-
-                        if !self.input.add_nsw_files_without_title_id {
-                            keep = re.is_match(file.name_encoded.as_str());
-                        }
-
-                        keep
-                    })
-                    .collect()
-            })
-            .flatten()
-            .collect();
-
-        pb.finish_with_message(&*format!("Scanned {} files", files.len()));
-
-        Ok(files)
+    let (name, paths) = account.split_once('=').ok_or_else(invalid)?;
+    let (credentials, token) = paths.split_once(',').ok_or_else(invalid)?;
+
+    if name.trim().is_empty() || credentials.is_empty() || token.is_empty() {
+        return Err(invalid());
     }
 
-    pub fn finalize(&self) -> std::io::Result<()> {
-        self.logger
-            .log_info(format!("Execution took {}", self.timer.elapsed().hhmmss()).as_str())
+    Ok(Account {
+        name: name.trim().to_string(),
+        credentials: PathBuf::from(credentials),
+        token: PathBuf::from(token),
+    })
+}
+
+fn parse_folder_account(mapping: &str) -> Result<(String, String), String> {
+    match mapping.split_once('=') {
+        Some((folder, account)) if !folder.trim().is_empty() && !account.trim().is_empty() => {
+            Ok((folder.trim().to_string(), account.trim().to_string()))
+        }
+        _ => Err(format!(
+            "Invalid folder account: {}, expected <folder_id>=<account_name>",
+            mapping
+        )),
     }
 }
 
 pub fn main() {
-    match real_main() {
+    let input = Input::from_args();
+    let log_format = input.log_format;
+
+    input.color.apply();
+
+    if let Err(e) = rustfoil::cancel::install_handler() {
+        let _ = Logger::new(LogLevel::Warning)
+            .with_format(log_format)
+            .log_warning(format!("Ctrl-C won't stop the run gracefully: {}", e).as_str());
+    }
+
+    let result = if input.verify_credentials {
+        rustfoil::verify_credentials(input.into()).map(|_| ())
+    } else if input.estimate {
+        rustfoil::estimate(input.into()).map(|_| ())
+    } else if input.benchmark_compression.is_some() {
+        rustfoil::benchmark_compression(input.into())
+    } else if !input.diff.is_empty() {
+        rustfoil::diff_indexes(input.into()).and_then(|diff| match diff.len() {
+            0 => Ok(()),
+            differences => Err(RustfoilError::IndexesDiffer(differences).into()),
+        })
+    } else if input.check.is_some() {
+        rustfoil::check_index(input.into())
+    } else if input.train_dict.is_some() {
+        rustfoil::train_dictionary(input.into()).map(|_| ())
+    } else {
+        // Progress bars would end up between the JSON lines
+        let progress: Box<dyn ProgressSink> = match log_format {
+            LogFormat::Text => Box::new(ProgressBars::default()),
+            LogFormat::Json => Box::new(NoProgress),
+        };
+
+        rustfoil::run_with_progress(input.into(), progress).map(|_| ())
+    };
+
+    match result {
         Ok(_) => std::process::exit(0),
-        Err(_) => std::process::exit(1),
+        Err(e) => {
+            let _ = Logger::new(LogLevel::Error)
+                .with_format(log_format)
+                .log_error(format!("{:#}", e).as_str());
+            std::process::exit(error::exit_code(&e))
+        }
     }
 }
 
-fn real_main() -> result::Result<()> {
-    let mut service = RustfoilService::new(Input::from_args());
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    service.validate_input()?;
+    fn parse(args: &[&str]) -> Result<Input, structopt::clap::Error> {
+        Input::from_iter_safe(std::iter::once("rustfoil").chain(args.iter().copied()))
+    }
 
-    service.init();
+    fn config(args: &[&str]) -> RustfoilConfig {
+        parse(args).unwrap().into()
+    }
 
-    let files = service.scan_folder()?;
+    #[test]
+    fn output_dir_conflicts_with_output_path() {
+        let error = parse(&["--output-dir", "out", "-o", "index.tfl"]).unwrap_err();
+        assert_eq!(error.kind, structopt::clap::ErrorKind::ArgumentConflict);
 
-    let index = service.generate_index(files.to_owned())?;
+        let config = config(&["--output-dir", "out", "--name-pattern", "{date}.tfl"]);
+        assert_eq!(config.output_dir, Some(PathBuf::from("out")));
+        assert_eq!(config.name_pattern, "{date}.tfl");
+    }
 
-    service.output_index(*index)?;
+    #[test]
+    fn url_map_parses_extension_and_prefix() {
+        assert_eq!(
+            parse_url_map(" .NSZ=myproxy:"),
+            Ok(("nsz".to_string(), "myproxy:".to_string()))
+        );
+        assert_eq!(
+            parse_url_map("nsp=http://host/?a=b"),
+            Ok(("nsp".to_string(), "http://host/?a=b".to_string()))
+        );
+        assert!(parse_url_map("nsz").is_err());
+        assert!(parse_url_map("=myproxy:").is_err());
+        assert!(parse_url_map("nsz=").is_err());
+    }
 
-    if service.input.share_files {
-        service.share_files(files)?;
+    #[test]
+    fn since_parses_timestamps_and_dates() {
+        assert_eq!(
+            parse_since("2024-03-01").unwrap(),
+            parse_since("2024-03-01T00:00:00Z").unwrap()
+        );
+        assert_eq!(
+            parse_since("2024-03-01T01:00:00+01:00").unwrap(),
+            parse_since("2024-03-01T00:00:00Z").unwrap()
+        );
+        assert!(parse_since("01.03.2024").is_err());
     }
 
-    if service.input.upload_my_drive || service.input.upload_folder_id.is_some() {
-        let (id, shared) = service.upload_index()?;
+    #[test]
+    fn accounts_and_folder_mappings_parse() {
+        let account = parse_account("work=creds.json,token.json").unwrap();
+        assert_eq!(account.name, "work");
+        assert_eq!(account.credentials, PathBuf::from("creds.json"));
+        assert_eq!(account.token, PathBuf::from("token.json"));
+        assert!(parse_account("work=creds.json").is_err());
+        assert!(parse_account("=creds.json,token.json").is_err());
+
+        assert_eq!(
+            parse_folder_account("1AbC = work"),
+            Ok(("1AbC".to_string(), "work".to_string()))
+        );
+        assert!(parse_folder_account("1AbC=").is_err());
+    }
 
-        if service.input.share_index {
-            service.share_index(id, shared)?;
+    #[test]
+    fn presets_replace_the_extension_set() {
+        for (preset, extensions) in [
+            ("--nsz-only", vec!["nsz"]),
+            ("--xci-only", vec!["xci", "xcz"]),
+            ("--installed-only", vec!["nsp", "nsz"]),
+        ] {
+            assert_eq!(config(&[preset]).extensions, extensions);
         }
-    };
+        assert_eq!(
+            config(&[]).extensions,
+            vec!["nsp", "nsz", "xci", "xcz", "nro"]
+        );
+        assert_eq!(
+            config(&["--extensions", "nsp,nro"]).extensions,
+            vec!["nsp", "nro"]
+        );
 
-    service.finalize()?;
+        for args in [
+            &["--nsz-only", "--xci-only"][..],
+            &["--nsz-only", "--installed-only"],
+            &["--xci-only", "--installed-only"],
+            &["--installed-only", "--extensions", "nsp"],
+        ] {
+            let error = parse(args).err().unwrap();
+            assert_eq!(error.kind, structopt::clap::ErrorKind::ArgumentConflict);
+        }
+    }
 
-    Ok(())
+    #[test]
+    fn title_id_style_is_chosen_on_the_command_line() {
+        for (style, expected) in [
+            ("bracket", TitleIdStyle::Bracket),
+            ("BRACE", TitleIdStyle::Brace),
+            ("bare16", TitleIdStyle::Bare16),
+            ("none", TitleIdStyle::None),
+        ] {
+            assert_eq!(
+                config(&["--title-id-style", style]).title_id_style,
+                expected
+            );
+        }
+        assert_eq!(config(&[]).title_id_style, TitleIdStyle::Bracket);
+        assert_eq!(
+            parse(&["--title-id-style", "paren"]).err().unwrap().kind,
+            structopt::clap::ErrorKind::ValueValidation
+        );
+
+        // A custom pattern replaces the style, so it's accepted without one
+        let config = config(&["--title-id-pattern", r"\(([0-9A-F]{16})\)"]);
+        assert_eq!(
+            config.title_id_pattern.as_deref(),
+            Some(r"\(([0-9A-F]{16})\)")
+        );
+        let error = parse(&["--title-id-pattern", "x", "--title-id-style", "brace"])
+            .err()
+            .unwrap();
+        assert_eq!(error.kind, structopt::clap::ErrorKind::ArgumentConflict);
+    }
+
+    #[test]
+    fn cdn_fallback_needs_the_header_name() {
+        let config = config(&[
+            "--cdn-fallback",
+            "https://cdn.example.com",
+            "--cdn-fallback-header",
+            "X-Mirror",
+        ]);
+        assert_eq!(
+            config.cdn_fallback,
+            Some((
+                "X-Mirror".to_string(),
+                "https://cdn.example.com".to_string()
+            ))
+        );
+
+        for args in [
+            &["--cdn-fallback", "https://cdn.example.com"],
+            &["--cdn-fallback-header", "X-Mirror"],
+        ] {
+            assert_eq!(
+                parse(args).err().unwrap().kind,
+                structopt::clap::ErrorKind::MissingRequiredArgument
+            );
+        }
+    }
 }
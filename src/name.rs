@@ -0,0 +1,276 @@
+use regex::Regex;
+use std::fmt;
+use std::str::FromStr;
+
+/// Matches region & language tags like `(USA)`, `(Europe)` or `(En,Fr,De)` with the space before them
+pub const REGION_TAG_PATTERN: &str = r"\s*\((?:(?:USA|US|Europe|EUR|EU|Japan|JPN|JP|World|Asia|Korea|KOR|KR|China|CHN|CN|Australia|AUS|En|Fr|De|Es|It|Nl|Pt|Ru|Ja|Ko|Zh)(?:\s*[,+]\s*)?)+\)";
+
+/// Removes every match of `tags` from the name, keeping its extension & squeezing the leftover spaces
+pub fn strip_tags(name: &str, tags: &Regex) -> String {
+    let (base, extension) = match name.rfind('.') {
+        Some(pos) => (&name[..pos], &name[pos..]),
+        None => (name, ""),
+    };
+
+    let stripped = tags.replace_all(base, "");
+    let mut name = stripped.split_whitespace().collect::<Vec<&str>>().join(" ");
+
+    // Keep names which consisted of tags only
+    if name.is_empty() {
+        return format!("{}{}", base, extension);
+    }
+
+    name.push_str(extension);
+    name
+}
+
+/// Naming scheme Title IDs are extracted from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TitleIdStyle {
+    /// `Title [0100000000010000][v0].nsp`
+    Bracket,
+    /// `Title {0100000000010000}.nsp`
+    Brace,
+    /// 16 hex digits standing on their own, e.g. `Title_0100000000010000_v0.nsp`
+    Bare16,
+    /// Names carry no Title ID
+    None,
+}
+
+impl TitleIdStyle {
+    /// Uppercased Title ID found in the name, the first one if there are several
+    pub fn extract(&self, name: &str) -> Option<String> {
+        match self {
+            TitleIdStyle::Bracket => delimited_title_id(name, '[', ']'),
+            TitleIdStyle::Brace => delimited_title_id(name, '{', '}'),
+            TitleIdStyle::Bare16 => bare_title_id(name),
+            TitleIdStyle::None => None,
+        }
+    }
+}
+
+impl FromStr for TitleIdStyle {
+    type Err = String;
+
+    fn from_str(style: &str) -> std::result::Result<Self, Self::Err> {
+        match style.to_lowercase().as_ref() {
+            "bracket" => Ok(TitleIdStyle::Bracket),
+            "brace" => Ok(TitleIdStyle::Brace),
+            "bare16" => Ok(TitleIdStyle::Bare16),
+            "none" => Ok(TitleIdStyle::None),
+            _ => Err(format!(
+                "Invalid Title ID style: {}, valid choices are bracket, brace, bare16 & none",
+                style
+            )),
+        }
+    }
+}
+
+impl fmt::Display for TitleIdStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TitleIdStyle::Bracket => write!(f, "bracket"),
+            TitleIdStyle::Brace => write!(f, "brace"),
+            TitleIdStyle::Bare16 => write!(f, "bare16"),
+            TitleIdStyle::None => write!(f, "none"),
+        }
+    }
+}
+
+fn is_title_id(candidate: &str) -> bool {
+    candidate.len() == 16 && candidate.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// First Title ID enclosed by `open` & `close`
+fn delimited_title_id(name: &str, open: char, close: char) -> Option<String> {
+    name.split(open)
+        .skip(1)
+        .filter_map(|group| group.find(close).map(|end| &group[..end]))
+        .find(|group| is_title_id(group))
+        .map(|title_id| title_id.to_uppercase())
+}
+
+/// First Title ID separated from the rest of the name by anything but letters & digits
+fn bare_title_id(name: &str) -> Option<String> {
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .find(|part| is_title_id(part))
+        .map(|title_id| title_id.to_uppercase())
+}
+
+/// Parts of a file name following the common `Title [TitleID][vVersion].ext` naming scheme
+pub struct NameParts {
+    pub title: Option<String>,
+    pub title_id: Option<String>,
+    pub version: Option<String>,
+    pub extension: Option<String>,
+}
+
+impl NameParts {
+    pub fn parse(name: &str) -> NameParts {
+        let (base, extension) = match name.rfind('.') {
+            Some(pos) => (&name[..pos], Some(name[pos + 1..].to_string())),
+            None => (name, None),
+        };
+
+        let title = base
+            .split(['[', '('])
+            .next()
+            .map(|title| title.trim().to_string())
+            .filter(|title| !title.is_empty());
+
+        let mut title_id = None;
+        let mut version = None;
+
+        for group in base.split('[').skip(1) {
+            let group = match group.find(']') {
+                Some(end) => &group[..end],
+                None => continue,
+            };
+
+            if title_id.is_none() && is_title_id(group) {
+                title_id = Some(group.to_uppercase());
+            } else if version.is_none()
+                && group.len() > 1
+                && group.starts_with(['v', 'V'])
+                && group[1..].chars().all(|c| c.is_ascii_digit())
+            {
+                version = Some(group[1..].to_string());
+            }
+        }
+
+        NameParts {
+            title,
+            title_id,
+            version,
+            extension,
+        }
+    }
+
+    /// Fills `{title}`, `{title_id}` & `{version}` in the template and appends the extension,
+    /// `None` if a used placeholder has no value for this name
+    pub fn render(&self, template: &str) -> Option<String> {
+        let mut name = template.to_string();
+
+        for (placeholder, value) in [
+            ("{title}", &self.title),
+            ("{title_id}", &self.title_id),
+            ("{version}", &self.version),
+        ] {
+            if name.contains(placeholder) {
+                name = name.replace(placeholder, value.as_ref()?);
+            }
+        }
+
+        if let Some(extension) = &self.extension {
+            name.push('.');
+            name.push_str(extension);
+        }
+
+        Some(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bracket_style_finds_the_bracketed_title_id() {
+        let style = TitleIdStyle::Bracket;
+
+        assert_eq!(
+            style.extract("Game [0100abcdef010000][v0].nsp"),
+            Some("0100ABCDEF010000".to_string())
+        );
+        assert_eq!(
+            style.extract("Game [v0][0100000000010000].nsp"),
+            Some("0100000000010000".to_string())
+        );
+        assert_eq!(style.extract("Game {0100000000010000}.nsp"), None);
+        assert_eq!(style.extract("Game [010000000001000].nsp"), None);
+    }
+
+    #[test]
+    fn brace_style_finds_the_braced_title_id() {
+        let style = TitleIdStyle::Brace;
+
+        assert_eq!(
+            style.extract("Game {0100000000010000}.nsp"),
+            Some("0100000000010000".to_string())
+        );
+        assert_eq!(style.extract("Game [0100000000010000].nsp"), None);
+    }
+
+    #[test]
+    fn bare16_style_finds_a_standalone_title_id() {
+        let style = TitleIdStyle::Bare16;
+
+        assert_eq!(
+            style.extract("Game_0100000000010000_v0.nsp"),
+            Some("0100000000010000".to_string())
+        );
+        assert_eq!(
+            style.extract("Game [0100000000010000].nsp"),
+            Some("0100000000010000".to_string())
+        );
+        assert_eq!(style.extract("Game01000000000100001.nsp"), None);
+    }
+
+    #[test]
+    fn none_style_never_finds_a_title_id() {
+        assert_eq!(
+            TitleIdStyle::None.extract("Game [0100000000010000].nsp"),
+            None
+        );
+    }
+
+    #[test]
+    fn styles_round_trip_through_their_names() {
+        for style in [
+            TitleIdStyle::Bracket,
+            TitleIdStyle::Brace,
+            TitleIdStyle::Bare16,
+            TitleIdStyle::None,
+        ] {
+            assert_eq!(style.to_string().parse::<TitleIdStyle>(), Ok(style));
+        }
+
+        assert!("parens".parse::<TitleIdStyle>().is_err());
+    }
+
+    #[test]
+    fn strips_region_and_language_tags() {
+        let tags = Regex::new(REGION_TAG_PATTERN).unwrap();
+
+        for (name, stripped) in [
+            (
+                "Game (USA) [0100000000010000][v0].nsp",
+                "Game [0100000000010000][v0].nsp",
+            ),
+            (
+                "Game (Europe) (En,Fr,De) [0100000000010000][v0].nsz",
+                "Game [0100000000010000][v0].nsz",
+            ),
+            (
+                "Game [0100000000010000][v0] (Japan).xci",
+                "Game [0100000000010000][v0].xci",
+            ),
+            (
+                "Game (USA + Europe) [0100000000010000].nsp",
+                "Game [0100000000010000].nsp",
+            ),
+            // Other parentheses aren't region tags
+            (
+                "Game (Deluxe Edition) [0100000000010000].nsp",
+                "Game (Deluxe Edition) [0100000000010000].nsp",
+            ),
+            ("(USA).nsp", "(USA).nsp"),
+        ] {
+            assert_eq!(strip_tags(name, &tags), stripped);
+            assert_eq!(
+                TitleIdStyle::Bracket.extract(&strip_tags(name, &tags)),
+                TitleIdStyle::Bracket.extract(name)
+            );
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use crate::index::ParsedFileInfo;
+use std::path::Path;
+
+/// Receives the progress of a run, e.g. to drive a UI. Every hook does nothing by default
+pub trait ProgressSink: Sync {
+    /// Scanning of the configured folders or URL started
+    fn on_scan_started(&self) {}
+
+    /// Called with the ID of every scanned folder & the name of each of its subfolders
+    fn on_folder_scanned(&self, _name: &str) {}
+
+    /// A file passed all filters and will be part of the index
+    fn on_file_found(&self, _file: &ParsedFileInfo) {}
+
+    fn on_scan_finished(&self, _files: usize) {}
+
+    /// Scanning was aborted by an error, which is returned by the run
+    fn on_scan_failed(&self) {}
+
+    /// Sharing started, `total` includes files which don't need to be shared
+    fn on_share_started(&self, _total: usize) {}
+
+    /// Called from the share workers whenever a file was handled, `done` counts up to `total`
+    fn on_share_progress(&self, _done: usize, _total: usize) {}
+
+    fn on_share_finished(&self, _shared: usize, _already_shared: usize) {}
+
+    /// An index file was written to disk
+    fn on_write_complete(&self, _path: &Path) {}
+
+    /// Called before every chunk of an upload is sent, `uploaded` counts the bytes sent once it
+    /// went through & reaches `total` with the last chunk. A resumed upload starts at the bytes
+    /// the server already has
+    fn on_upload_progress(&self, _uploaded: u64, _total: u64) {}
+
+    /// The upload finished or failed, which is returned by the run
+    fn on_upload_finished(&self) {}
+}
+
+/// Ignores all progress
+pub struct NoProgress;
+
+impl ProgressSink for NoProgress {}
@@ -0,0 +1,108 @@
+use indicatif::{ProgressBar, ProgressStyle};
+use rustfoil::progress::ProgressSink;
+use std::sync::Mutex;
+
+/// Shows a spinner while scanning & a progress bar while uploading & sharing
+#[derive(Default)]
+pub struct ProgressBars {
+    bar: Mutex<Option<ProgressBar>>,
+}
+
+impl ProgressBars {
+    fn start(&self, pb: ProgressBar) {
+        *self.bar.lock().unwrap() = Some(pb);
+    }
+
+    fn with_bar(&self, f: impl FnOnce(&ProgressBar)) {
+        if let Some(pb) = self.bar.lock().unwrap().as_ref() {
+            f(pb);
+        }
+    }
+
+    fn finish(&self, f: impl FnOnce(&ProgressBar)) {
+        if let Some(pb) = self.bar.lock().unwrap().take() {
+            f(&pb);
+        }
+    }
+}
+
+impl ProgressSink for ProgressBars {
+    fn on_scan_started(&self) {
+        let pb = ProgressBar::new(!0);
+        pb.enable_steady_tick(130);
+        pb.set_style(
+            ProgressStyle::default_spinner()
+                // For more spinners check out the cli-spinners project:
+                // https://github.com/sindresorhus/cli-spinners/blob/master/spinners.json
+                .tick_strings(&["-", "\\", "|", "/"])
+                .template("{spinner:.blue} {msg}"),
+        );
+        pb.set_message("Scanning...");
+
+        self.start(pb);
+    }
+
+    fn on_folder_scanned(&self, name: &str) {
+        self.with_bar(|pb| pb.set_message(format!("Scanning {}", name).as_str()));
+    }
+
+    fn on_scan_finished(&self, files: usize) {
+        self.finish(|pb| pb.finish_with_message(&format!("Scanned {} files", files)));
+    }
+
+    fn on_scan_failed(&self) {
+        self.finish(|pb| pb.finish_and_clear());
+    }
+
+    fn on_share_started(&self, total: usize) {
+        let pb = ProgressBar::new(total as u64);
+
+        pb.set_style(
+            ProgressStyle::default_bar()
+                .template("[{elapsed_precise}] [{bar:40.cyan/blue}] {msg} {pos:>7}/{len:7} Files")
+                .progress_chars("#>-"),
+        );
+
+        pb.set_message("Sharing");
+
+        self.start(pb);
+    }
+
+    fn on_share_progress(&self, done: usize, _total: usize) {
+        // Workers report concurrently, so a smaller count may arrive late
+        self.with_bar(|pb| pb.set_position(pb.position().max(done as u64)));
+    }
+
+    fn on_upload_progress(&self, uploaded: u64, total: u64) {
+        let mut bar = self.bar.lock().unwrap();
+        let pb = bar.get_or_insert_with(|| {
+            let pb = ProgressBar::new(total);
+
+            pb.set_style(
+                ProgressStyle::default_bar()
+                    .template(
+                        "[{elapsed_precise}] [{bar:40.cyan/blue}] {msg} {bytes:>9}/{total_bytes:9}",
+                    )
+                    .progress_chars("#>-"),
+            );
+
+            pb.set_message("Uploading");
+            pb
+        });
+
+        pb.set_position(uploaded);
+    }
+
+    fn on_upload_finished(&self) {
+        self.finish(|pb| pb.finish_and_clear());
+    }
+
+    fn on_share_finished(&self, shared: usize, already_shared: usize) {
+        self.finish(|pb| {
+            pb.finish_with_message(&format!(
+                "Shared {} files, {} were already shared",
+                shared, already_shared
+            ))
+        });
+    }
+}
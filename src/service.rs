@@ -0,0 +1,3886 @@
+use crate::cache::{ListingOptions, ScanCache, ShareCache};
+use crate::cancel;
+use crate::compression::{self, CompressionFlag, ZstdOptions};
+use crate::encryption::{self, EncryptionFlag, AES_KEY_SIZE};
+use crate::error::RustfoilError;
+use crate::gdrive::{
+    parse_folder_id, ApiCalls, AuthDelegate, FileFields, FileInfo, FolderEstimate, GDriveService,
+    HttpProxy, RequestLimiter, Trashed, ESTIMATE_PAGE_SIZE,
+};
+use crate::header;
+use crate::http::HttpService;
+use crate::index::{self, DedupeStrategy, FileEntry, Index, IndexDiff, ParsedFileInfo};
+use crate::logging::Logger;
+use crate::name::{self, TitleIdStyle};
+use crate::progress::{NoProgress, ProgressSink};
+use crate::result;
+use crate::size;
+use crate::storage::{self, S3Target, StorageTarget};
+use crate::tinfoil::{convert_to_tinfoil_format, decode_tinfoil_format};
+use crate::token::TokenStore;
+use crate::{Account, Report, RustfoilConfig};
+use anyhow::Error;
+use hhmmss::Hhmmss;
+use regex::{Captures, Regex};
+use reqwest::Url;
+use sha2::{Digest, Sha256};
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use yup_oauth2::{ApplicationSecret, FlowType};
+
+/// `output_path` writing the index to stdout
+const STDOUT_PATH: &str = "-";
+
+/// Default dictionary size of the zstd CLI
+const ZSTD_DICT_MAX_SIZE: usize = 110 * 1024;
+
+/// Lists the files of a folder, possibly from the scan cache
+type FolderLister<'a> = dyn FnMut(&str, &mut Option<ScanCache>) -> std::result::Result<Vec<FileInfo>, RustfoilError>
+    + 'a;
+
+pub struct RustfoilService {
+    logger: Logger,
+    config: RustfoilConfig,
+    gdrive: Option<GDriveService>,
+    /// Services of the additional accounts, keyed by account name
+    accounts: HashMap<String, GDriveService>,
+    /// Compiled `title_id_pattern`, or why it doesn't compile
+    title_id_regex: std::result::Result<Option<Regex>, String>,
+    region_tag_regex: Option<Regex>,
+    title_id_allowlist: Option<HashSet<String>>,
+    title_id_denylist: Option<HashSet<String>>,
+    zstd_dict: Option<Vec<u8>>,
+    aes_key: Option<[u8; AES_KEY_SIZE]>,
+    progress: Box<dyn ProgressSink>,
+    storage: Vec<Box<dyn StorageTarget>>,
+    api_calls: Arc<ApiCalls>,
+    /// Shared by all Drive services, so scanning, sharing & uploading stay under one limit
+    request_limiter: Arc<RequestLimiter>,
+    timer: Instant,
+}
+
+/// Uploads to Google Drive & shares the uploaded index if configured
+struct DriveTarget<'a> {
+    service: &'a RustfoilService,
+}
+
+impl StorageTarget for DriveTarget<'_> {
+    fn upload(&self, path: &Path) -> result::Result<String> {
+        let (id, shared) = self.service.upload_index(path)?;
+
+        if self.service.config.share_index {
+            self.service.share_index(id.to_owned(), shared)?;
+        }
+
+        Ok(id)
+    }
+}
+
+impl RustfoilService {
+    pub fn new(config: RustfoilConfig) -> RustfoilService {
+        let mut logger = Logger::new(config.log_level).with_format(config.log_format);
+
+        if config.list
+            || config.output_dir.is_none() && config.output_path.as_os_str() == STDOUT_PATH
+        {
+            logger = logger.to_stderr();
+        }
+
+        RustfoilService {
+            logger,
+            timer: Instant::now(),
+            gdrive: None,
+            accounts: HashMap::new(),
+            // An invalid pattern is reported by `validate_config` & anything extracting Title IDs
+            title_id_regex: RustfoilService::compile_title_id_regex(
+                config.title_id_pattern.as_deref(),
+            ),
+            region_tag_regex: if config.strip_region_tags {
+                RustfoilService::compile_region_tag_regex(config.region_tag_regex.as_deref()).ok()
+            } else {
+                None
+            },
+            title_id_allowlist: None,
+            title_id_denylist: None,
+            zstd_dict: None,
+            aes_key: None,
+            progress: Box::new(NoProgress),
+            storage: Vec::new(),
+            api_calls: Arc::default(),
+            request_limiter: Arc::new(RequestLimiter::new(config.max_concurrent_requests)),
+            config,
+        }
+    }
+
+    /// Additionally uploads the written index files to `target`
+    pub fn with_storage(mut self, target: Box<dyn StorageTarget>) -> RustfoilService {
+        self.storage.push(target);
+        self
+    }
+
+    /// Reports the progress of the run to `progress` instead of ignoring it
+    pub fn with_progress(mut self, progress: Box<dyn ProgressSink>) -> RustfoilService {
+        self.progress = progress;
+        self
+    }
+
+    /// Whether the default account is needed, folders mapped to other accounts don't need it
+    pub fn needs_gdrive(&self) -> bool {
+        self.config
+            .folder_ids
+            .iter()
+            .any(|id| self.folder_account(id).is_none())
+            || self.config.upload_my_drive
+            || self.config.upload_folder_id.is_some()
+    }
+
+    pub fn init(&mut self) -> result::Result<()> {
+        if let Some(path) = &self.config.title_id_allowlist {
+            let allowlist = RustfoilService::read_title_ids(path.as_path())?;
+
+            self.logger.log_debug(
+                format!("Loaded {} Title IDs from the allowlist", allowlist.len()).as_str(),
+            )?;
+
+            self.title_id_allowlist = Some(allowlist);
+        }
+
+        if let Some(path) = &self.config.title_id_denylist {
+            let denylist = RustfoilService::read_title_ids(path.as_path())?;
+
+            self.logger.log_debug(
+                format!("Loaded {} Title IDs from the denylist", denylist.len()).as_str(),
+            )?;
+
+            self.title_id_denylist = Some(denylist);
+        }
+
+        if let Some(path) = &self.config.zstd_dict {
+            if let CompressionFlag::Zstd | CompressionFlag::Auto = self.config.compression {
+                self.zstd_dict =
+                    Some(std::fs::read(path).map_err(RustfoilError::input_read(path))?);
+                self.logger.log_warning(
+                    "Compressing with a zstd dictionary, only clients which have the same dictionary can read the index",
+                )?;
+            } else {
+                self.logger
+                    .log_warning("Ignoring the zstd dictionary as zstd compression isn't used")?;
+            }
+        }
+
+        if let Some(key) = &self.config.aes_key {
+            self.aes_key = Some(encryption::parse_aes_key(key)?);
+        }
+
+        if let CompressionFlag::Zstd | CompressionFlag::Auto = self.config.compression {
+            self.logger.log_debug(
+                format!("Compressing with {} zstd threads", self.zstd_threads()).as_str(),
+            )?;
+        }
+
+        for id in &self.config.folder_ids {
+            if let Some(account) = self.folder_account(id) {
+                if !self.accounts.contains_key(&account.name) {
+                    let gdrive = self.create_gdrive_for(Some(account))?;
+                    self.accounts.insert(account.name.to_owned(), gdrive);
+                }
+            }
+        }
+
+        if !self.needs_gdrive() {
+            return Ok(());
+        }
+
+        self.gdrive = Some(self.create_gdrive()?);
+
+        Ok(())
+    }
+
+    /// Account the folder is mapped to via `folder_accounts`, `None` for the default account
+    fn folder_account(&self, folder_id: &str) -> Option<&Account> {
+        let folder_id = parse_folder_id(folder_id);
+        let (_, name) = self
+            .config
+            .folder_accounts
+            .iter()
+            .find(|(folder, _)| parse_folder_id(folder) == folder_id)?;
+
+        self.config
+            .accounts
+            .iter()
+            .find(|account| &account.name == name)
+    }
+
+    /// Service of the account scanning the folder, once `init` created them
+    fn gdrive_for(&self, folder_id: &str) -> &GDriveService {
+        match self.folder_account(folder_id) {
+            Some(account) => &self.accounts[&account.name],
+            None => self.gdrive.as_ref().unwrap(),
+        }
+    }
+
+    fn create_gdrive(&self) -> result::Result<GDriveService> {
+        self.create_gdrive_for(None)
+    }
+
+    /// Creates a service for `account`, `None` uses the default credentials & token
+    fn create_gdrive_for(&self, account: Option<&Account>) -> result::Result<GDriveService> {
+        let flow_type = if self.config.auth_device_code {
+            FlowType::Device(yup_oauth2::GOOGLE_DEVICE_CODE_URL.to_string())
+        } else if self.config.headless {
+            FlowType::InstalledInteractive
+        } else {
+            FlowType::InstalledRedirect(3333)
+        };
+
+        let proxy = HttpProxy::resolve(self.config.proxy.as_deref())?;
+
+        if let Some(proxy) = &proxy {
+            self.logger.log_debug(
+                format!(
+                    "Sending Drive requests via proxy {}:{}",
+                    proxy.host, proxy.port
+                )
+                .as_str(),
+            )?;
+        }
+
+        let (secret, token_store) = match account {
+            Some(account) => (
+                RustfoilService::read_credentials_file(account.credentials.as_path())?,
+                TokenStore::from_file(account.token.as_path())
+                    .map_err(RustfoilError::input_read(account.token.as_path()))?,
+            ),
+            None => (self.read_credentials()?, self.read_token_store()?),
+        };
+
+        Ok(GDriveService::new(
+            secret,
+            token_store,
+            flow_type,
+            AuthDelegate::new(
+                self.logger.to_owned(),
+                Duration::from_secs(self.config.auth_timeout),
+            ),
+            Duration::from_secs(self.config.timeout),
+            Arc::clone(&self.api_calls),
+            proxy.as_ref(),
+        )
+        .with_fields(self.file_fields())
+        .with_limiter(Arc::clone(&self.request_limiter)))
+    }
+
+    /// Optional file fields needed by the configured filters
+    fn file_fields(&self) -> FileFields {
+        FileFields {
+            modified_time: self.config.since.is_some() || self.config.max_total_size.is_some(),
+            owned_by_me: self.config.owner_only,
+            properties: self.config.version_from_metadata.is_some(),
+        }
+    }
+
+    /// Reads one Title ID per line, empty lines & lines starting with `#` are ignored
+    fn read_title_ids(path: &Path) -> result::Result<HashSet<String>> {
+        let mut title_ids = HashSet::new();
+
+        for line in std::fs::read_to_string(path)
+            .map_err(RustfoilError::input_read(path))?
+            .lines()
+        {
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            if line.len() != 16 || !line.chars().all(|c| c.is_ascii_hexdigit()) {
+                return Err(Error::new(RustfoilError::InvalidTitleId(line.to_string())));
+            }
+
+            title_ids.insert(line.to_uppercase());
+        }
+
+        Ok(title_ids)
+    }
+
+    fn read_env(name: &str) -> Option<String> {
+        std::env::var(name)
+            .ok()
+            .filter(|value| !value.trim().is_empty())
+    }
+
+    fn read_credentials(&self) -> result::Result<ApplicationSecret> {
+        Ok(
+            match RustfoilService::read_env(&self.config.credentials_env) {
+                Some(json) => {
+                    self.logger.log_debug(
+                        format!("Using credentials from ${}", self.config.credentials_env).as_str(),
+                    )?;
+                    yup_oauth2::parse_application_secret(&json)?
+                }
+                None => RustfoilService::read_credentials_file(self.config.credentials.as_path())?,
+            },
+        )
+    }
+
+    /// Reads a credentials file, which may be gzip-compressed
+    fn read_credentials_file(path: &Path) -> result::Result<ApplicationSecret> {
+        let json = compression::read_to_string_maybe_gzip(path)
+            .map_err(RustfoilError::input_read(path))?;
+        Ok(yup_oauth2::parse_application_secret(&json)?)
+    }
+
+    fn read_token_store(&self) -> result::Result<TokenStore> {
+        Ok(match RustfoilService::read_env(&self.config.token_env) {
+            Some(json) => {
+                self.logger
+                    .log_debug(format!("Using token from ${}", self.config.token_env).as_str())?;
+                TokenStore::from_json(json.as_str())?
+            }
+            None => TokenStore::from_file(self.config.token.as_path())
+                .map_err(RustfoilError::input_read(self.config.token.as_path()))?,
+        })
+    }
+
+    fn ensure_credentials(&self) -> std::result::Result<(), RustfoilError> {
+        if !self.config.credentials.exists()
+            && RustfoilService::read_env(&self.config.credentials_env).is_none()
+        {
+            return Err(RustfoilError::CredentialsMissing);
+        }
+
+        Ok(())
+    }
+
+    pub fn validate_config(&self) -> result::Result<()> {
+        let uploads_to_drive =
+            self.config.upload_my_drive || self.config.upload_folder_id.is_some();
+
+        for (conflicting, message) in [
+            (
+                self.config.share_index && !uploads_to_drive,
+                "--share-index only shares an index uploaded via --upload-my-drive or --upload-folder-id",
+            ),
+            (
+                self.config.upload_my_drive && self.config.upload_folder_id.is_some(),
+                "--upload-my-drive & --upload-folder-id can't be combined, pick one destination",
+            ),
+            (
+                self.config.resume_upload && !uploads_to_drive,
+                "--resume-upload needs --upload-my-drive or --upload-folder-id",
+            ),
+            (
+                self.config.s3_bucket.is_none()
+                    && (self.config.s3_key.is_some() || self.config.s3_endpoint.is_some()),
+                "--s3-key & --s3-endpoint need --s3-bucket",
+            ),
+            (
+                self.config.split_base_url.is_some() && self.config.split_size.is_none(),
+                "--split-base-url needs --split-size",
+            ),
+            (
+                self.config.no_index && self.config.urls_only.is_none(),
+                "--no-index needs --urls-only, otherwise nothing would be written",
+            ),
+            (
+                self.config.embed_metadata && self.config.deterministic,
+                "--embed-metadata records the current time, it can't be combined with --deterministic",
+            ),
+            (
+                self.extracts_no_title_ids()
+                    && (self.config.title_id_allowlist.is_some()
+                        || self.config.title_id_denylist.is_some()),
+                "--title-id-style none finds no Title IDs to match the allow- or denylist against",
+            ),
+        ] {
+            if conflicting {
+                return Err(Error::new(RustfoilError::ConflictingOptions(
+                    message.to_string(),
+                )));
+            }
+        }
+
+        for (_, name) in &self.config.folder_accounts {
+            if !self
+                .config
+                .accounts
+                .iter()
+                .any(|account| &account.name == name)
+            {
+                return Err(Error::new(RustfoilError::UnknownAccount(name.to_owned())));
+            }
+        }
+
+        for account in &self.config.accounts {
+            if !account.credentials.exists() {
+                return Err(Error::new(RustfoilError::AccountCredentialsMissing {
+                    name: account.name.to_owned(),
+                    path: account.credentials.to_owned(),
+                }));
+            }
+        }
+
+        if self.config.per_folder {
+            for (set, option) in [
+                (self.config.from_stdin, "--from-stdin"),
+                (self.config.http_url.is_some(), "--http-url"),
+                (self.writes_to_stdout(), "--output-path -"),
+                (self.config.list, "--list"),
+            ] {
+                if set {
+                    return Err(Error::new(RustfoilError::ConflictingOptions(format!(
+                        "--per-folder only indexes Drive folders, it can't be combined with {}",
+                        option
+                    ))));
+                }
+            }
+        }
+
+        let indexless_mode = if self.config.list {
+            Some("--list")
+        } else if self.writes_to_stdout() {
+            Some("writing to stdout")
+        } else if self.config.no_index {
+            Some("--no-index")
+        } else {
+            None
+        };
+
+        if let Some(mode) = indexless_mode {
+            for (set, option) in [
+                (self.config.split_size.is_some(), "--split-size"),
+                (self.config.gzip_sidecar, "--gzip-sidecar"),
+                (self.config.checksum_sidecar, "--checksum-sidecar"),
+                (self.config.upload_my_drive, "--upload-my-drive"),
+                (self.config.upload_folder_id.is_some(), "--upload-folder-id"),
+                (self.config.s3_bucket.is_some(), "--s3-bucket"),
+            ] {
+                if set {
+                    return Err(Error::new(RustfoilError::ConflictingOptions(format!(
+                        "{} needs an index file, it can't be combined with {}",
+                        option, mode
+                    ))));
+                }
+            }
+        }
+
+        if self.needs_gdrive() {
+            self.ensure_credentials()?;
+        }
+
+        // Checked early as well so an existing index doesn't only fail the run after scanning,
+        // names from a pattern are only known once the index is generated
+        if indexless_mode.is_none() && self.config.output_dir.is_none() && !self.config.per_folder {
+            self.ensure_overwritable(self.config.output_path.as_path())?;
+        }
+
+        if let Some(referrer) = &self.config.referrer {
+            header::normalize_referrer(referrer)?;
+        }
+
+        for header in self.config.headers.iter().flatten() {
+            header::normalize_header(header)?;
+        }
+
+        if let Some((name, url)) = &self.config.cdn_fallback {
+            header::cdn_fallback_header(name, url)?;
+        }
+
+        HttpProxy::resolve(self.config.proxy.as_deref())?;
+
+        self.title_id_regex()?;
+
+        if self.config.strip_region_tags {
+            RustfoilService::compile_region_tag_regex(self.config.region_tag_regex.as_deref())?;
+        }
+
+        if self.config.s3_bucket.is_some()
+            && (RustfoilService::read_env("AWS_ACCESS_KEY_ID").is_none()
+                || RustfoilService::read_env("AWS_SECRET_ACCESS_KEY").is_none())
+        {
+            return Err(Error::new(RustfoilError::S3CredentialsMissing));
+        }
+
+        if let Some(key) = &self.config.aes_key {
+            encryption::parse_aes_key(key)?;
+        } else if self.config.deterministic && self.config.public_key.is_some() {
+            return Err(Error::new(RustfoilError::MissingAesKey));
+        }
+
+        Ok(())
+    }
+
+    /// Configured regex finding Title IDs in file names, `None` if the Title ID style is used.
+    /// Fails if the configured pattern doesn't compile
+    pub fn title_id_regex(&self) -> result::Result<Option<&Regex>> {
+        match &self.title_id_regex {
+            Ok(re) => Ok(re.as_ref()),
+            Err(reason) => Err(Error::new(RustfoilError::InvalidTitleIdPattern(
+                reason.to_owned(),
+            ))),
+        }
+    }
+
+    /// Uppercased Title ID of the file name, found by the configured pattern or Title ID style.
+    /// Callers check `title_id_regex` first, as no Title ID is found with an invalid pattern
+    pub fn title_id(&self, name: &str) -> Option<String> {
+        match &self.title_id_regex {
+            Err(_) => None,
+            Ok(Some(re)) => re
+                .captures(name)
+                .and_then(|captures| captures.get(1))
+                .map(|title_id| title_id.as_str().to_uppercase()),
+            Ok(None) => self.config.title_id_style.extract(name),
+        }
+    }
+
+    /// Whether Title IDs are deliberately not extracted, via `--title-id-style none`
+    fn extracts_no_title_ids(&self) -> bool {
+        self.config.title_id_pattern.is_none() && self.config.title_id_style == TitleIdStyle::None
+    }
+
+    /// Compiles `pattern`, failing with the reason it's an invalid Title ID pattern
+    fn compile_title_id_regex(pattern: Option<&str>) -> std::result::Result<Option<Regex>, String> {
+        let pattern = match pattern {
+            Some(pattern) => pattern,
+            None => return Ok(None),
+        };
+
+        let re = Regex::new(pattern).map_err(|e| e.to_string())?;
+
+        if re.captures_len() < 2 {
+            return Err("the first group has to capture the Title ID".to_string());
+        }
+
+        Ok(Some(re))
+    }
+
+    fn compile_region_tag_regex(pattern: Option<&str>) -> result::Result<Regex> {
+        Ok(Regex::new(pattern.unwrap_or(name::REGION_TAG_PATTERN))
+            .map_err(|e| RustfoilError::InvalidRegionTagPattern(e.to_string()))?)
+    }
+
+    pub fn generate_index(&self, files: Vec<ParsedFileInfo>) -> result::Result<Box<Index>> {
+        self.title_id_regex()?;
+
+        let mut index = Box::new(Index::new());
+
+        let mut index_files: Vec<FileEntry> = Vec::new();
+
+        for mut info in files {
+            if let Some(tags) = &self.region_tag_regex {
+                info.strip_tags(tags);
+            }
+
+            if self.config.normalize_extensions {
+                info.lowercase_extension();
+            }
+
+            let url = match &info.url {
+                Some(url) => format!("{}#{}", url, info.name_encoded),
+                None => format!(
+                    "{}{}#{}",
+                    self.url_prefix(&info),
+                    info.id,
+                    info.name_encoded
+                ),
+            };
+
+            if self.config.debug_names {
+                self.logger
+                    .log_trace(format!("{} -> {}", info.name, info.name_encoded).as_str())?;
+            }
+
+            let size = match info.size.parse::<u64>() {
+                Ok(size) => size,
+                Err(_) if !self.config.strict_size => {
+                    self.logger.log_warning(
+                        format!(
+                            "Skipping {} as its size {:?} isn't valid",
+                            info.name, info.size
+                        )
+                        .as_str(),
+                    )?;
+                    continue;
+                }
+                Err(_) => {
+                    return Err(Error::new(RustfoilError::InvalidSize {
+                        name: info.name,
+                        size: info.size,
+                    }))
+                }
+            };
+
+            index_files.push(FileEntry::new(url, size));
+        }
+
+        if self.config.deterministic {
+            index_files.sort_by(|a, b| a.url().cmp(b.url()));
+        }
+
+        let count = index_files.len();
+        let total_size: u64 = index_files.iter().map(|file| file.size()).sum();
+
+        index.files = Some(index_files);
+
+        self.logger.log_debug("Added files to index")?;
+
+        if let Some(warning) = self.size_warning(count) {
+            self.logger.log_warning(warning.as_str())?;
+        }
+
+        if let Some(success) = &self.config.success {
+            index.success = Some(
+                self.fill_placeholders(success, count, total_size)?
+                    .replace("\\n", "\n")
+                    .replace("\\t", "\t"),
+            );
+            self.logger.log_debug("Added success message to index")?;
+        }
+
+        if let Some(referrer) = &self.config.referrer {
+            index.referrer = Some(header::normalize_referrer(referrer)?);
+            self.logger.log_debug("Added referrer to index")?;
+        }
+
+        if self.config.embed_metadata {
+            index.generated_at = Some(chrono::Utc::now().to_rfc3339());
+            index.generator = Some(format!("rustfoil {}", env!("CARGO_PKG_VERSION")));
+            self.logger
+                .log_debug("Added generation metadata to index")?;
+        }
+
+        if let Some(keys) = &self.config.google_api_key {
+            index.google_api_key = Some(keys.to_string());
+            self.logger.log_debug("Added google api key to index")?;
+        }
+
+        if let Some(keys) = &self.config.one_fichier_keys {
+            let mut normalized = Vec::new();
+
+            for key in keys {
+                match header::normalize_one_fichier_key(key) {
+                    Ok(key) => normalized.push(key),
+                    Err(e) if self.config.strict_keys => return Err(Error::new(e)),
+                    Err(e) => {
+                        self.logger.log_warning(format!("{}", e).as_str())?;
+                        normalized.push(key.trim().to_string());
+                    }
+                }
+            }
+
+            index.one_fichier_keys = Some(normalized);
+            self.logger.log_debug("Added 1Fichier keys to index")?;
+        }
+
+        if let Some(headers) = &self.config.headers {
+            index.headers = Some(
+                headers
+                    .iter()
+                    .map(|header| header::normalize_header(header))
+                    .collect::<std::result::Result<_, _>>()?,
+            );
+            self.logger.log_debug("Added headers to index")?;
+        }
+
+        if let Some((name, url)) = &self.config.cdn_fallback {
+            index
+                .headers
+                .get_or_insert_with(Vec::new)
+                .push(header::cdn_fallback_header(name, url)?);
+            self.logger
+                .log_debug("Added CDN fallback header to index")?;
+        }
+
+        if let Some(directories) = &self.config.directories {
+            index.add_directories(directories.iter().cloned());
+            self.logger.log_debug("Added directories to index")?;
+        }
+
+        if let Some(version) = &self.config.min_version {
+            index.version = Some(version.to_owned());
+            self.logger.log_debug("Added minimum version to index")?;
+        }
+
+        if let Some(theme) = &self.config.theme_blacklist {
+            index.theme_blacklist = Some(theme.to_owned());
+            self.logger.log_debug("Added theme blacklist to index")?;
+        }
+
+        if let Some(theme) = &self.config.theme_whitelist {
+            index.theme_whitelist = Some(theme.to_owned());
+            self.logger.log_debug("Added theme whitelist to index")?;
+        }
+
+        if let Some(error) = &self.config.theme_error {
+            index.theme_error = Some(self.fill_placeholders(error, count, total_size)?);
+            self.logger
+                .log_debug("Added theme error message to index")?;
+        }
+
+        self.logger.log_info("Generated index successfully")?;
+
+        Ok(index)
+    }
+
+    /// Prefix of the file's extension from `url_map`, `url_prefix` if it has none
+    fn url_prefix(&self, info: &ParsedFileInfo) -> &str {
+        let extension = info.extension();
+
+        self.config
+            .url_map
+            .iter()
+            .find(|(mapped, _)| *mapped == extension)
+            .map_or(self.config.url_prefix.as_str(), |(_, prefix)| {
+                prefix.as_str()
+            })
+    }
+
+    /// Replaces `{count}`, `{size}` & `{date}` in a message, unknown placeholders are kept as is
+    fn fill_placeholders(
+        &self,
+        message: &str,
+        count: usize,
+        total_size: u64,
+    ) -> result::Result<String> {
+        let mut unknown = Vec::new();
+
+        let filled = Regex::new(r"\{(\w+)\}")?.replace_all(message, |captures: &Captures| {
+            match &captures[1] {
+                "count" => count.to_string(),
+                "size" => size::format_size(total_size),
+                "date" => chrono::Local::now().format("%Y-%m-%d").to_string(),
+                _ => {
+                    unknown.push(captures[0].to_string());
+                    captures[0].to_string()
+                }
+            }
+        });
+
+        for placeholder in &unknown {
+            self.logger.log_warning(
+                format!("Unknown placeholder {} is kept as is", placeholder).as_str(),
+            )?;
+        }
+
+        Ok(filled.to_string())
+    }
+
+    fn writes_to_stdout(&self) -> bool {
+        self.config.output_dir.is_none() && self.config.output_path.as_os_str() == STDOUT_PATH
+    }
+
+    /// Writes the index (or its parts when splitting) to disk and returns the written paths,
+    /// none when writing to stdout
+    pub fn output_index(&self, index: &Index) -> result::Result<Vec<PathBuf>> {
+        if self.writes_to_stdout() {
+            self.write_index_to(index, &mut std::io::stdout().lock())?;
+            return Ok(Vec::new());
+        }
+
+        let split_size = match self.config.split_size {
+            Some(split_size) => split_size,
+            None => {
+                self.write_index(index, self.config.output_path.as_path())?;
+                return Ok(vec![self.config.output_path.to_owned()]);
+            }
+        };
+
+        let mut parts = index.split(split_size)?;
+
+        // Other indexes are loaded once through the parent rather than by every part
+        if self.config.split_base_url.is_some() {
+            for part in &mut parts {
+                part.directories = None;
+            }
+        }
+        let mut paths = Vec::new();
+
+        for (i, part) in parts.iter().enumerate() {
+            let path = self.part_path(i + 1);
+            self.write_index(part, path.as_path())?;
+            paths.push(path);
+        }
+
+        self.logger
+            .log_info(format!("Wrote index in {} parts", parts.len()).as_str())?;
+
+        if let Some(base_url) = &self.config.split_base_url {
+            let mut parent = index.to_owned();
+            parent.files = None;
+
+            // The parts are listed after the --directory entries
+            parent.add_directories(paths.iter().map(|path| {
+                format!(
+                    "{}{}",
+                    base_url,
+                    path.file_name().unwrap().to_str().unwrap()
+                )
+            }));
+
+            self.write_index(&parent, self.config.output_path.as_path())?;
+            paths.insert(0, self.config.output_path.to_owned());
+        }
+
+        Ok(paths)
+    }
+
+    /// Path of the n-th split part, e.g. `index_1.tfl` for `index.tfl`
+    fn part_path(&self, part: usize) -> PathBuf {
+        RustfoilService::suffixed_path(self.config.output_path.as_path(), part.to_string().as_str())
+    }
+
+    /// Path with `_<suffix>` appended to its file stem
+    fn suffixed_path(path: &Path, suffix: &str) -> PathBuf {
+        let stem = path.file_stem().unwrap().to_str().unwrap();
+
+        path.with_file_name(match path.extension() {
+            Some(extension) => format!("{}_{}.{}", stem, suffix, extension.to_str().unwrap()),
+            None => format!("{}_{}", stem, suffix),
+        })
+    }
+
+    fn write_index(&self, index: &Index, path: &Path) -> result::Result<()> {
+        self.ensure_overwritable(path)?;
+
+        if self.config.gzip_sidecar {
+            self.ensure_overwritable(RustfoilService::sidecar_path(path, "gz").as_path())?;
+        }
+
+        if self.config.checksum_sidecar {
+            self.ensure_overwritable(RustfoilService::sidecar_path(path, "sha256").as_path())?;
+        }
+
+        let bytes = self.encode_index(index)?;
+
+        self.write_atomically(path, &bytes)?;
+        self.progress.on_write_complete(path);
+
+        self.log_written(
+            format!("{} to disk", path.file_name().unwrap().to_str().unwrap()).as_str(),
+            &bytes,
+        )?;
+
+        if self.config.gzip_sidecar {
+            let path = RustfoilService::sidecar_path(path, "gz");
+
+            self.write_atomically(&path, &compression::gzip(&bytes)?)?;
+
+            self.logger
+                .log_info(format!("Wrote gzip sidecar to {}", path.display()).as_str())?;
+        }
+
+        if self.config.checksum_sidecar {
+            let sidecar = RustfoilService::sidecar_path(path, "sha256");
+            let digest = storage::hex(&Sha256::digest(&bytes));
+
+            // Same format as sha256sum, so `sha256sum -c` can verify the index
+            self.write_atomically(
+                &sidecar,
+                format!(
+                    "{}  {}\n",
+                    digest,
+                    path.file_name().unwrap().to_str().unwrap()
+                )
+                .as_bytes(),
+            )?;
+
+            self.logger
+                .log_info(format!("Wrote SHA-256 checksum to {}", sidecar.display()).as_str())?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the URL of every file of the index, one per line
+    fn write_url_list(&self, index: &Index, path: &Path) -> result::Result<()> {
+        self.ensure_overwritable(path)?;
+
+        let files: Vec<&FileEntry> = index.files.iter().flatten().collect();
+        let contents: String = files
+            .iter()
+            .map(|file| format!("{}\n", file.url()))
+            .collect();
+
+        self.write_atomically(path, contents.as_bytes())?;
+
+        self.logger
+            .log_info(format!("Wrote {} URLs to {}", files.len(), path.display()).as_str())?;
+
+        Ok(())
+    }
+
+    /// Prints every file of the index as a JSON object per line to stdout
+    fn print_entries(&self, index: &Index) -> result::Result<()> {
+        RustfoilService::write_entries(index, &mut std::io::stdout().lock())
+    }
+
+    /// Writes every entry of the index as one JSON object per line
+    fn write_entries(index: &Index, writer: &mut dyn Write) -> result::Result<()> {
+        for file in index.files.iter().flatten() {
+            writeln!(writer, "{}", serde_json::to_string(file)?)?;
+        }
+
+        writer.flush()?;
+
+        Ok(())
+    }
+
+    /// Writes the index as a single file to `writer`, e.g. stdout or an in-memory buffer
+    pub fn write_index_to(&self, index: &Index, writer: &mut dyn Write) -> result::Result<()> {
+        let bytes = self.encode_index(index)?;
+
+        writer.write_all(&bytes)?;
+        writer.flush()?;
+
+        self.log_written("index to stdout", &bytes)
+    }
+
+    fn encode_index(&self, index: &Index) -> result::Result<Vec<u8>> {
+        let json = index.to_json(self.config.schema)?;
+        let compression = self.config.compression.to_owned();
+        let encryption = match self.config.public_key {
+            None => EncryptionFlag::NoEncrypt,
+            Some(_) => EncryptionFlag::Encrypt,
+        };
+
+        let bytes = convert_to_tinfoil_format(
+            json.as_str(),
+            compression,
+            encryption,
+            self.config.public_key.to_owned(),
+            self.aes_key.as_ref(),
+            self.config.rsa_padding,
+            ZstdOptions {
+                dictionary: self.zstd_dict.as_deref(),
+                threads: self.zstd_threads(),
+            },
+        )?;
+
+        // The header flag follows the "TINFOIL" magic
+        let used = CompressionFlag::from_flag(bytes[7]).unwrap_or(compression);
+
+        if let CompressionFlag::Auto = compression {
+            self.logger
+                .log_info(format!("Picked {} as the smallest compression", used).as_str())?;
+        }
+
+        self.logger.log_debug(
+            format!(
+                "Wrote {} of JSON as {} ({:.1}% of the original size)",
+                size::format_size(json.len() as u64),
+                size::format_size(bytes.len() as u64),
+                bytes.len() as f64 / json.len().max(1) as f64 * 100.0
+            )
+            .as_str(),
+        )?;
+
+        Ok(bytes)
+    }
+
+    fn log_written(&self, target: &str, bytes: &[u8]) -> result::Result<()> {
+        let used = CompressionFlag::from_flag(bytes[7]).unwrap_or(CompressionFlag::Off);
+        let encryption = match self.config.public_key {
+            None => EncryptionFlag::NoEncrypt,
+            Some(_) => EncryptionFlag::Encrypt,
+        };
+
+        self.logger.log_info(
+            format!(
+                "Finished writing {target}, using {compression} compression{dictionary} & {encryption}encryption",
+                target = target,
+                compression = match used {
+                    CompressionFlag::Off => "no".to_string(),
+                    _ => used.to_string(),
+                },
+                dictionary = match (used, &self.zstd_dict) {
+                    (CompressionFlag::Zstd, Some(_)) => " with a dictionary",
+                    _ => "",
+                },
+                encryption = match encryption {
+                    EncryptionFlag::NoEncrypt => "no ",
+                    EncryptionFlag::Encrypt => "",
+                }
+            )
+            .as_str(),
+        )?;
+
+        Ok(())
+    }
+
+    /// Resolves `compression_threads`, 0 uses one thread per CPU
+    fn zstd_threads(&self) -> u32 {
+        match self.config.compression_threads {
+            0 => std::thread::available_parallelism().map_or(1, |threads| threads.get() as u32),
+            threads => threads,
+        }
+    }
+
+    fn ensure_overwritable(&self, path: &Path) -> std::result::Result<(), RustfoilError> {
+        if !self.config.overwrite && path.exists() {
+            return Err(RustfoilError::OutputExists(path.to_owned()));
+        }
+
+        Ok(())
+    }
+
+    /// Writes a table of the name, size & Title ID of every file in the index
+    fn write_report(&self, index: &Index, path: &Path) -> result::Result<()> {
+        let rows: Vec<(String, String, String)> = index
+            .files
+            .iter()
+            .flatten()
+            .map(|file| {
+                let name = file.name();
+                let title_id = self
+                    .title_id(name.as_str())
+                    .unwrap_or_else(|| "-".to_string());
+
+                (name, size::format_size(file.size()), title_id)
+            })
+            .collect();
+
+        let name_width = rows
+            .iter()
+            .map(|row| row.0.chars().count())
+            .fold(4, usize::max);
+        let size_width = rows.iter().map(|row| row.1.len()).fold(4, usize::max);
+
+        let mut report = format!(
+            "{:name_width$}  {:>size_width$}  Title ID\n",
+            "Name",
+            "Size",
+            name_width = name_width,
+            size_width = size_width
+        );
+
+        for (name, size, title_id) in &rows {
+            report.push_str(
+                format!(
+                    "{:name_width$}  {:>size_width$}  {}\n",
+                    name,
+                    size,
+                    title_id,
+                    name_width = name_width,
+                    size_width = size_width
+                )
+                .as_str(),
+            );
+        }
+
+        self.write_atomically(path, report.as_bytes())?;
+
+        self.logger.log_info(
+            format!("Wrote report of {} files to {}", rows.len(), path.display()).as_str(),
+        )?;
+
+        Ok(())
+    }
+
+    /// Writes to a temporary file next to `path` first, so readers never see a partially written file
+    fn write_atomically(&self, path: &Path, bytes: &[u8]) -> result::Result<()> {
+        let mut temp_path = path.to_owned().into_os_string();
+        temp_path.push(".tmp");
+        let temp_path = PathBuf::from(temp_path);
+
+        let result =
+            std::fs::write(&temp_path, bytes).and_then(|_| std::fs::rename(&temp_path, path));
+
+        if let Err(source) = result {
+            let _ = std::fs::remove_file(&temp_path);
+
+            return Err(Error::new(RustfoilError::OutputWrite {
+                path: path.to_owned(),
+                source,
+            }));
+        }
+
+        Ok(())
+    }
+
+    /// Path next to `path` with `extension` appended to its full file name
+    fn sidecar_path(path: &Path, extension: &str) -> PathBuf {
+        let mut path = path.to_owned().into_os_string();
+        path.push(".");
+        path.push(extension);
+        PathBuf::from(path)
+    }
+
+    pub fn share_file(&self, file_id: String, is_shared: &bool) -> result::Result<()> {
+        if !is_shared || self.config.reshare {
+            let gdrive = self.gdrive.as_ref().unwrap();
+
+            gdrive
+                .share_file(file_id.as_str())
+                .map_err(|e| gdrive.map_error(e))?;
+        }
+
+        Ok(())
+    }
+
+    /// Shares all files not shared yet using `share_concurrency` workers, failures are reported once all are done
+    pub fn share_files(&self, files: &[ParsedFileInfo]) -> result::Result<()> {
+        self.progress.on_share_started(files.len());
+
+        let mut share_cache = match &self.config.share_cache {
+            Some(_) if self.config.refresh_share_cache => Some(ShareCache::default()),
+            Some(path) => Some(ShareCache::load(path.as_path())?),
+            None => None,
+        };
+
+        let is_shared = |file: &ParsedFileInfo| {
+            file.shared
+                || share_cache
+                    .as_ref()
+                    .is_some_and(|cache| cache.contains(file.id.as_str()))
+        };
+
+        // Files served over plain HTTP have nothing to share on Google Drive
+        let drive_files = files.iter().filter(|file| file.url.is_none());
+        let already_shared = drive_files
+            .clone()
+            .filter(|file| is_shared(file) && !self.config.reshare)
+            .count();
+        let pending: Vec<&ParsedFileInfo> = drive_files
+            .filter(|file| !is_shared(file) || self.config.reshare)
+            .collect();
+
+        let skipped = files.len() - pending.len();
+        self.progress.on_share_progress(skipped, files.len());
+
+        // The Drive hub isn't thread safe, so every worker gets its own for each account
+        let workers = self.share_workers(pending.len());
+        let accounts: BTreeSet<Option<&str>> =
+            pending.iter().map(|file| file.account.as_deref()).collect();
+        let services = (0..workers)
+            .map(|_| {
+                accounts
+                    .iter()
+                    .map(|name| {
+                        let account = name.and_then(|name| {
+                            self.config
+                                .accounts
+                                .iter()
+                                .find(|account| account.name == name)
+                        });
+
+                        Ok((*name, self.create_gdrive_for(account)?))
+                    })
+                    .collect::<result::Result<HashMap<_, _>>>()
+            })
+            .collect::<result::Result<Vec<_>>>()?;
+
+        let (shared, failures) = self.share_concurrently(
+            &pending,
+            services,
+            skipped,
+            files.len(),
+            &|gdrives: &HashMap<Option<&str>, GDriveService>, file| {
+                let gdrive = &gdrives[&file.account.as_deref()];
+
+                gdrive
+                    .share_file(file.id.as_str())
+                    .map(|_| ())
+                    .map_err(|e| gdrive.map_error(e))
+            },
+        );
+
+        if let (Some(cache), Some(path)) = (&mut share_cache, &self.config.share_cache) {
+            for file_id in &shared {
+                cache.insert(file_id);
+            }
+
+            cache.save(path.as_path())?;
+        }
+
+        self.progress
+            .on_share_finished(shared.len(), already_shared);
+
+        cancel::check()?;
+
+        if failures.is_empty() {
+            return Ok(());
+        }
+
+        for (name, error) in &failures {
+            self.logger
+                .log_error(format!("Failed to share {}: {}", name, error).as_str())?;
+        }
+
+        Err(Error::new(RustfoilError::ShareFailed {
+            failed: failures.len(),
+            total: pending.len(),
+        }))
+    }
+
+    /// Warning for indexes with more files than `--warn-threshold`, unless they're split anyway
+    fn size_warning(&self, count: usize) -> Option<String> {
+        if count <= self.config.warn_threshold || self.config.split_size.is_some() {
+            return None;
+        }
+
+        Some(format!(
+            "The index has {} files, some Tinfoil builds struggle with more than {}, consider using --split-size",
+            count, self.config.warn_threshold
+        ))
+    }
+
+    /// Number of share workers, at least one & no more than there are files to share
+    fn share_workers(&self, pending: usize) -> usize {
+        self.config.share_concurrency.clamp(1, pending.max(1))
+    }
+
+    /// Shares the pending files from one thread per worker, `share` gets the worker's own Drive
+    /// services. Returns the IDs of the shared files & the names of the failed ones with the error
+    fn share_concurrently<'a, W: Send>(
+        &self,
+        pending: &[&'a ParsedFileInfo],
+        workers: Vec<W>,
+        skipped: usize,
+        total: usize,
+        share: &(dyn Fn(&W, &ParsedFileInfo) -> std::result::Result<(), RustfoilError> + Sync),
+    ) -> (Vec<&'a str>, Vec<(String, RustfoilError)>) {
+        let next = AtomicUsize::new(0);
+        let done = AtomicUsize::new(skipped);
+        let progress = self.progress.as_ref();
+        let failures = Mutex::new(Vec::new());
+        let shared = Mutex::new(Vec::new());
+
+        std::thread::scope(|scope| {
+            for worker in workers {
+                let (next, done, failures, shared) = (&next, &done, &failures, &shared);
+
+                scope.spawn(move || {
+                    while let Some(file) = pending
+                        .get(next.fetch_add(1, Ordering::SeqCst))
+                        .filter(|_| !cancel::is_cancelled())
+                    {
+                        match share(&worker, file) {
+                            Ok(_) => shared.lock().unwrap().push(file.id.as_str()),
+                            Err(e) => failures.lock().unwrap().push((file.name.to_owned(), e)),
+                        }
+
+                        progress.on_share_progress(done.fetch_add(1, Ordering::SeqCst) + 1, total);
+                    }
+                });
+            }
+        });
+
+        (shared.into_inner().unwrap(), failures.into_inner().unwrap())
+    }
+
+    pub fn upload_index(&self, input: &Path) -> result::Result<(String, bool)> {
+        let folder_id = &self.config.upload_folder_id;
+
+        // `needs_gdrive` makes `init` create the default service whenever uploading
+        let res = self.gdrive.as_ref().unwrap().upload_file(
+            input,
+            &self.config.upload_folder_id,
+            RustfoilService::sidecar_path(input, "upload").as_path(),
+            self.config.resume_upload,
+            &self.logger,
+            self.progress.as_ref(),
+        );
+        self.progress.on_upload_finished();
+        let res = res?;
+
+        self.logger.log_info(
+            format!(
+                "Uploaded {file} to {destination}",
+                file = input.file_name().unwrap_or_default().to_string_lossy(),
+                destination = folder_id
+                    .as_ref()
+                    .unwrap_or("My Drive".to_string().borrow())
+            )
+            .as_str(),
+        )?;
+
+        Ok(res)
+    }
+
+    pub fn share_index(&self, file_id: String, is_shared: bool) -> result::Result<()> {
+        self.share_file(file_id, &is_shared)?;
+        self.logger.log_info("Shared Index File")?;
+
+        Ok(())
+    }
+
+    fn keep_file(&self, file: &ParsedFileInfo) -> bool {
+        if self.config.no_filter {
+            return true;
+        }
+
+        let extension = file.extension();
+
+        if !self.config.add_non_nsw_files
+            && !self.config.extensions.iter().any(|allowed| {
+                allowed
+                    .trim_start_matches('.')
+                    .eq_ignore_ascii_case(&extension)
+            })
+        {
+            return false;
+        }
+
+        // Homebrew legitimately lacks a Title ID
+        if !self.config.add_nsw_files_without_title_id
+            && !self.extracts_no_title_ids()
+            && extension != "nro"
+            && self.title_id(file.name.as_str()).is_none()
+        {
+            return false;
+        }
+
+        // Checked first, so denied Title IDs stay out even when allowlisted
+        if let Some(denylist) = &self.title_id_denylist {
+            let denied = self
+                .title_id(file.name.as_str())
+                .is_some_and(|title_id| denylist.contains(&title_id));
+
+            if denied {
+                let _ = self.logger.log_debug(
+                    format!("Skipping {} as its Title ID is denylisted", file.name).as_str(),
+                );
+                return false;
+            }
+        }
+
+        if let Some(allowlist) = &self.title_id_allowlist {
+            let allowed = self
+                .title_id(file.name.as_str())
+                .is_some_and(|title_id| allowlist.contains(&title_id));
+
+            if !allowed {
+                let _ = self.logger.log_debug(
+                    format!("Skipping {} as its Title ID isn't allowlisted", file.name).as_str(),
+                );
+                return false;
+            }
+        }
+
+        if let Some(since) = &self.config.since {
+            let modified = file
+                .modified_time
+                .as_ref()
+                .and_then(|time| chrono::DateTime::parse_from_rfc3339(time).ok());
+
+            let keep = match modified {
+                Some(modified) => modified >= *since,
+                None => self.config.include_unknown_mtime,
+            };
+
+            if !keep {
+                let _ = self.logger.log_debug(
+                    format!(
+                        "Skipping {} as it wasn't modified since {}",
+                        file.name, since
+                    )
+                    .as_str(),
+                );
+                return false;
+            }
+        }
+
+        if self.config.min_size.is_some() || self.config.max_size.is_some() {
+            let size = file.size.parse::<u64>().unwrap_or(0);
+
+            if size < self.config.min_size.unwrap_or(0)
+                || size > self.config.max_size.unwrap_or(u64::MAX)
+            {
+                let _ = self.logger.log_debug(
+                    format!(
+                        "Skipping {} as its size of {} is out of range",
+                        file.name,
+                        size::format_size(size)
+                    )
+                    .as_str(),
+                );
+                return false;
+            }
+        }
+
+        true
+    }
+
+    pub fn scan_folder(&mut self) -> result::Result<Vec<ParsedFileInfo>> {
+        self.title_id_regex()?;
+
+        if self.config.folder_ids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Trigger Authentication if needed
+        for gdrive in self.gdrive.iter().chain(self.accounts.values()) {
+            gdrive.trigger_auth().map_err(|e| gdrive.map_error(e))?;
+        }
+
+        self.progress.on_scan_started();
+
+        // A folder's modifiedTime changes neither with files changing in its subfolders nor with
+        // files being shared, so listings depending on either can't be trusted from the cache
+        let mut cache = match &self.config.cache {
+            Some(_) if !self.config.no_recursion || self.config.share_files => {
+                self.logger.log_warning(
+                    "Not using the scan cache, it's only used with --no-recursion & without --share-files",
+                )?;
+                None
+            }
+            Some(path) => Some(ScanCache::load(path.as_path())?),
+            None => None,
+        };
+
+        let (files, google_native, not_owned) = self
+            .collect_folders(&mut cache, &mut |id, cache| {
+                self.list_folder(self.gdrive_for(id), id, cache)
+            })?;
+
+        if let (Some(cache), Some(path)) = (&cache, &self.config.cache) {
+            cache.save(path.as_path())?;
+        }
+
+        self.progress.on_scan_finished(files.len());
+
+        if self.config.report_trashed {
+            self.report_trashed()?;
+        }
+
+        if google_native > 0 {
+            self.logger
+                .log_info(format!("Skipped {} Google-native files", google_native).as_str())?;
+        }
+
+        if not_owned > 0 {
+            self.logger
+                .log_info(format!("Skipped {} files you don't own", not_owned).as_str())?;
+        }
+
+        Ok(files)
+    }
+
+    /// Lists every configured folder with `list` & filters the listings. A failing folder is
+    /// skipped with `continue_on_error` and fails the scan otherwise. Returns the files with the
+    /// number of skipped Google-native & not owned files
+    fn collect_folders(
+        &self,
+        cache: &mut Option<ScanCache>,
+        list: &mut FolderLister,
+    ) -> result::Result<(Vec<ParsedFileInfo>, usize, usize)> {
+        let mut files: Vec<ParsedFileInfo> = Vec::new();
+        let mut google_native = 0;
+        let mut not_owned = 0;
+
+        for id in self.config.folder_ids.iter().map(|id| parse_folder_id(id)) {
+            if let Err(e) = cancel::check() {
+                // Folders listed so far are still worth keeping
+                if let (Some(cache), Some(path)) = (&*cache, &self.config.cache) {
+                    cache.save(path.as_path())?;
+                }
+
+                self.progress.on_scan_failed();
+                return Err(Error::new(e));
+            }
+
+            let account = self
+                .folder_account(id.as_str())
+                .map(|account| &account.name);
+
+            match list(id.as_str(), cache) {
+                Ok(folder_files) => files.extend(self.filter_listing(
+                    folder_files,
+                    account,
+                    &mut google_native,
+                    &mut not_owned,
+                )),
+                Err(e) => {
+                    let error = RustfoilError::DriveScan {
+                        folder_id: id.to_owned(),
+                        source: Box::new(e),
+                    };
+
+                    if !self.config.continue_on_error {
+                        self.progress.on_scan_failed();
+                        return Err(Error::new(error));
+                    }
+
+                    self.logger
+                        .log_error(format!("{:#}, skipping it", Error::new(error)).as_str())?;
+                }
+            }
+        }
+
+        Ok((files, google_native, not_owned))
+    }
+
+    /// Drops shortcuts, Google-native & not owned files, counting the latter two, and the files
+    /// `keep_file` rejects. `no_filter` keeps the whole listing
+    fn filter_listing(
+        &self,
+        listing: Vec<FileInfo>,
+        account: Option<&String>,
+        google_native: &mut usize,
+        not_owned: &mut usize,
+    ) -> Vec<ParsedFileInfo> {
+        let no_filter = self.config.no_filter;
+
+        listing
+            .into_iter()
+            .filter(|info| {
+                if no_filter || !info.is_shortcut() {
+                    return true;
+                }
+
+                let _ = self
+                    .logger
+                    .log_debug(format!("Skipping shortcut {}", info.name).as_str());
+                false
+            })
+            .filter(|info| {
+                let skip =
+                    !no_filter && !self.config.include_google_native && info.is_google_native();
+                *google_native += skip as usize;
+                !skip
+            })
+            // Listings cached before ownership was tracked count as owned
+            .filter(|info| {
+                let skip = !no_filter && self.config.owner_only && info.owned_by_me == Some(false);
+                *not_owned += skip as usize;
+                !skip
+            })
+            .map(|info| {
+                ParsedFileInfo::new(
+                    info,
+                    self.config.name_template.as_deref(),
+                    self.config.include_path,
+                    self.config.version_from_metadata.as_deref(),
+                )
+            })
+            .map(|file| ParsedFileInfo {
+                account: account.cloned(),
+                ..file
+            })
+            .filter(|file| self.keep_file(file))
+            .inspect(|file| self.progress.on_file_found(file))
+            .collect()
+    }
+
+    fn list_folder(
+        &self,
+        gdrive: &GDriveService,
+        folder_id: &str,
+        cache: &mut Option<ScanCache>,
+    ) -> std::result::Result<Vec<FileInfo>, RustfoilError> {
+        let recursion = !self.config.no_recursion;
+        let follow_shortcuts = self.config.follow_shortcuts;
+        let trashed = if self.config.include_trashed {
+            Trashed::Include
+        } else {
+            Trashed::Exclude
+        };
+        let on_folder = |name: &str| self.progress.on_folder_scanned(name);
+        let on_cycle = |name: &str, id: &str| self.warn_cycle(name, id);
+
+        on_folder(folder_id);
+
+        let cache = match cache {
+            Some(cache) => cache,
+            None => {
+                return gdrive
+                    .get_all_files_in_folder(
+                        folder_id,
+                        recursion,
+                        follow_shortcuts,
+                        trashed,
+                        &on_folder,
+                        &on_cycle,
+                    )
+                    .map_err(|e| gdrive.map_error(e))
+            }
+        };
+
+        let options = ListingOptions {
+            recursion,
+            follow_shortcuts,
+            include_trashed: self.config.include_trashed,
+            fields: Some(self.file_fields().mask()),
+        };
+
+        let modified_time = gdrive
+            .get_modified_time(folder_id)
+            .map_err(|e| gdrive.map_error(e))?;
+
+        if !self.config.no_cache {
+            if let Some(files) = cache.get(
+                folder_id,
+                &modified_time,
+                &options,
+                Duration::from_secs(self.config.cache_ttl),
+            ) {
+                let _ = self
+                    .logger
+                    .log_debug(format!("Using cached listing of folder {}", folder_id).as_str());
+                return Ok(files.to_owned());
+            }
+        }
+
+        let files = gdrive
+            .get_all_files_in_folder(
+                folder_id,
+                recursion,
+                follow_shortcuts,
+                trashed,
+                &on_folder,
+                &on_cycle,
+            )
+            .map_err(|e| gdrive.map_error(e))?;
+
+        cache.insert(folder_id, modified_time, options, files.to_owned());
+
+        Ok(files)
+    }
+
+    /// Warns about a folder reached a second time while scanning, which is skipped
+    fn warn_cycle(&self, name: &str, id: &str) {
+        let _ = self.logger.log_warning(
+            format!(
+                "Skipping folder {} ({}) as it was already scanned, it's reachable through more than one path",
+                name, id
+            )
+            .as_str(),
+        );
+    }
+
+    /// Logs the trashed files of the configured folders which would pass the filters
+    fn report_trashed(&self) -> result::Result<()> {
+        let mut trashed = 0;
+
+        for id in self.config.folder_ids.iter().map(|id| parse_folder_id(id)) {
+            let gdrive = self.gdrive_for(id.as_str());
+            let files = gdrive
+                .get_all_files_in_folder(
+                    id.as_str(),
+                    !self.config.no_recursion,
+                    self.config.follow_shortcuts,
+                    Trashed::Only,
+                    &|_| {},
+                    &|name, id| self.warn_cycle(name, id),
+                )
+                .map_err(|e| gdrive.map_error(e))?;
+
+            for file in files
+                .into_iter()
+                .map(|info| {
+                    ParsedFileInfo::new(
+                        info,
+                        self.config.name_template.as_deref(),
+                        self.config.include_path,
+                        self.config.version_from_metadata.as_deref(),
+                    )
+                })
+                .filter(|file| self.keep_file(file))
+            {
+                trashed += 1;
+                self.logger
+                    .log_info(format!("Trashed: {} ({})", file.name, file.id).as_str())?;
+            }
+        }
+
+        self.logger.log_info(
+            format!(
+                "Found {} trashed files which would have been indexed",
+                trashed
+            )
+            .as_str(),
+        )?;
+
+        Ok(())
+    }
+
+    pub fn scan_http(&self) -> result::Result<Vec<ParsedFileInfo>> {
+        self.title_id_regex()?;
+
+        let url = match &self.config.http_url {
+            Some(url) => url,
+            None => return Ok(Vec::new()),
+        };
+
+        self.progress.on_scan_started();
+        self.progress.on_folder_scanned(url);
+
+        let files: Vec<ParsedFileInfo> =
+            match HttpService::new(Duration::from_secs(self.config.timeout))
+                .and_then(|http| http.get_all_files(url.as_str(), !self.config.no_recursion))
+            {
+                Ok(files) => files,
+                Err(e) => {
+                    self.progress.on_scan_failed();
+                    return Err(e);
+                }
+            }
+            .into_iter()
+            .map(|info| ParsedFileInfo::new_http(info, self.config.name_template.as_deref()))
+            .filter(|file| self.keep_file(file))
+            .inspect(|file| self.progress.on_file_found(file))
+            .collect();
+
+        self.progress.on_scan_finished(files.len());
+
+        Ok(files)
+    }
+
+    /// Reads `URL<TAB>size[<TAB>name]` lines, the name defaults to the last segment of the URL
+    pub fn read_file_list(&self, reader: impl BufRead) -> result::Result<Vec<ParsedFileInfo>> {
+        self.title_id_regex()?;
+
+        let mut files = Vec::new();
+
+        for (i, line) in reader.lines().enumerate() {
+            let line = line?;
+
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let invalid = |reason: &str| {
+                Error::new(RustfoilError::InvalidFileListLine {
+                    line: i + 1,
+                    reason: reason.to_string(),
+                })
+            };
+
+            let mut columns = line.split('\t');
+
+            // Not parsed as URL as Tinfoil also accepts schemes like `1f:`
+            let url = columns.next().unwrap_or("").trim();
+
+            if url.is_empty() {
+                return Err(invalid("missing URL"));
+            }
+
+            let size = columns
+                .next()
+                .ok_or_else(|| invalid("missing size"))?
+                .trim()
+                .parse::<u64>()
+                .map_err(|_| invalid("size isn't a number"))?;
+
+            let name = match columns.next().map(|name| name.trim()) {
+                Some(name) if !name.is_empty() => name.to_string(),
+                _ => match Url::parse(url) {
+                    Ok(url) => HttpService::file_name(&url),
+                    Err(_) => url.rsplit('/').next().unwrap_or(url).to_string(),
+                },
+            };
+
+            files.push(ParsedFileInfo::new_http(
+                FileInfo::new(url.to_string(), size.to_string(), name, true),
+                self.config.name_template.as_deref(),
+            ));
+        }
+
+        self.logger
+            .log_info(format!("Read {} files from stdin", files.len()).as_str())?;
+
+        Ok(files)
+    }
+
+    /// Drops every file which duplicates an earlier one according to `strategy`
+    fn dedupe(
+        &self,
+        files: Vec<ParsedFileInfo>,
+        strategy: DedupeStrategy,
+    ) -> result::Result<Vec<ParsedFileInfo>> {
+        let mut seen = HashSet::new();
+        let mut kept = Vec::with_capacity(files.len());
+
+        for file in files {
+            let key = match strategy {
+                DedupeStrategy::TitleId => self.title_id(file.name.as_str()),
+                DedupeStrategy::Name => Some(file.name.to_owned()),
+                DedupeStrategy::Url => {
+                    Some(file.url.to_owned().unwrap_or_else(|| file.id.to_owned()))
+                }
+            };
+
+            if key.is_some_and(|key| !seen.insert(key)) {
+                self.logger.log_debug(
+                    format!("Skipping {} as a duplicate by {}", file.name, strategy).as_str(),
+                )?;
+            } else {
+                kept.push(file);
+            }
+        }
+
+        Ok(kept)
+    }
+
+    /// Keeps the newest files as long as their summed size stays within `budget`, the older ones
+    /// are dropped. Files without a modification time count as the oldest, the kept files stay in
+    /// scan order
+    fn cap_total_size(
+        &self,
+        files: Vec<ParsedFileInfo>,
+        budget: u64,
+    ) -> result::Result<Vec<ParsedFileInfo>> {
+        let mut newest_first: Vec<usize> = (0..files.len()).collect();
+        newest_first.sort_by(|&a, &b| files[b].modified_time.cmp(&files[a].modified_time));
+
+        let mut keep = vec![false; files.len()];
+        let mut total: u64 = 0;
+
+        for i in newest_first {
+            let size = files[i].size.parse::<u64>().unwrap_or(0);
+
+            if total.saturating_add(size) > budget {
+                break;
+            }
+
+            total += size;
+            keep[i] = true;
+        }
+
+        let mut kept = Vec::new();
+        let mut excluded = Vec::new();
+
+        for (file, keep) in files.into_iter().zip(keep) {
+            if keep {
+                kept.push(file);
+            } else {
+                self.logger.log_debug(
+                    format!("Excluding {} to stay within --max-total-size", file.name).as_str(),
+                )?;
+                excluded.push(file);
+            }
+        }
+
+        if !excluded.is_empty() {
+            self.logger.log_info(
+                format!(
+                    "Excluded the {} oldest files ({}) to stay within {}, keeping {}",
+                    excluded.len(),
+                    size::format_size(index::total_size(&excluded)),
+                    size::format_size(budget),
+                    size::format_size(total)
+                )
+                .as_str(),
+            )?;
+        }
+
+        Ok(kept)
+    }
+
+    pub fn warn_duplicates(&self, files: &[ParsedFileInfo]) -> result::Result<()> {
+        self.title_id_regex()?;
+
+        let mut title_ids: BTreeMap<String, Vec<&str>> = BTreeMap::new();
+
+        for file in files {
+            if let Some(title_id) = self.title_id(file.name.as_str()) {
+                title_ids
+                    .entry(title_id)
+                    .or_default()
+                    .push(file.name.as_str());
+            }
+        }
+
+        for (title_id, names) in title_ids {
+            if names.len() > 1 {
+                self.logger.log_warning(
+                    format!(
+                        "Title ID {} appears in {} files: {}",
+                        title_id,
+                        names.len(),
+                        names.join(", ")
+                    )
+                    .as_str(),
+                )?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn finalize(&self) -> std::io::Result<()> {
+        let calls = &self.api_calls;
+
+        if calls.total() > 0 {
+            self.logger.log_debug(
+                format!(
+                    "Made {} Google Drive API calls: {} list, {} metadata, {} share & {} upload",
+                    calls.total(),
+                    calls.list.load(Ordering::Relaxed),
+                    calls.metadata.load(Ordering::Relaxed),
+                    calls.share.load(Ordering::Relaxed),
+                    calls.upload.load(Ordering::Relaxed)
+                )
+                .as_str(),
+            )?;
+        }
+
+        self.logger
+            .log_info(format!("Execution took {}", self.timer.elapsed().hhmmss()).as_str())
+    }
+
+    /// Trains a zstd dictionary from `dict_samples` and writes it to `train_dict`
+    pub fn train_dictionary(&self) -> result::Result<PathBuf> {
+        let path = match &self.config.train_dict {
+            Some(path) => path.to_owned(),
+            None => return Err(Error::msg("No path to write the dictionary to was given")),
+        };
+
+        let dictionary =
+            compression::train_dictionary(&self.config.dict_samples, ZSTD_DICT_MAX_SIZE)?;
+
+        self.write_atomically(path.as_path(), &dictionary)?;
+
+        self.logger.log_info(
+            format!(
+                "Trained a {} dictionary from {} samples, written to {}",
+                size::format_size(dictionary.len() as u64),
+                self.config.dict_samples.len(),
+                path.display()
+            )
+            .as_str(),
+        )?;
+
+        Ok(path)
+    }
+
+    /// Authorizes with Google Drive & returns the email address of the account
+    pub fn verify_credentials(&self) -> result::Result<String> {
+        self.ensure_credentials()?;
+
+        let gdrive = self.create_gdrive()?;
+
+        let email = gdrive
+            .get_account_email()
+            .map_err(|e| match gdrive.map_error(e) {
+                RustfoilError::Drive(message) => RustfoilError::Auth(message),
+                error => error,
+            })?
+            .unwrap_or_else(|| "an unknown account".to_string());
+
+        self.logger
+            .log_info(format!("Authorized as {}", email).as_str())?;
+
+        Ok(email)
+    }
+
+    /// Fetches a single page of every configured folder to log a rough file count
+    pub fn estimate(&self) -> result::Result<Vec<FolderEstimate>> {
+        if self.config.folder_ids.is_empty() {
+            return Err(Error::msg("No folders to estimate were given"));
+        }
+
+        self.ensure_credentials()?;
+
+        let mut estimates = Vec::new();
+
+        for id in self.config.folder_ids.iter().map(|id| parse_folder_id(id)) {
+            let gdrive = self.create_gdrive_for(self.folder_account(id.as_str()))?;
+            let estimate = gdrive
+                .estimate_folder(id.as_str())
+                .map_err(|e| gdrive.map_error(e))?;
+
+            self.logger
+                .log_info(RustfoilService::estimate_message(&estimate).as_str())?;
+
+            if estimate.incomplete {
+                self.logger.log_warning(
+                    format!(
+                        "Drive didn't search all of folder {}, it may have more files",
+                        id
+                    )
+                    .as_str(),
+                )?;
+            }
+
+            estimates.push(estimate);
+        }
+
+        self.logger
+            .log_info("Files in subfolders aren't counted, the full scan may find more")?;
+
+        Ok(estimates)
+    }
+
+    fn estimate_message(estimate: &FolderEstimate) -> String {
+        format!(
+            "Folder {} has {}{} files & {} subfolders{}",
+            estimate.folder_id,
+            if estimate.more { "at least " } else { "" },
+            estimate.files,
+            estimate.folders,
+            if estimate.more {
+                format!(
+                    ", it's a large folder with more than {} entries",
+                    ESTIMATE_PAGE_SIZE
+                )
+            } else {
+                String::new()
+            }
+        )
+    }
+
+    /// Logs size & time of every compression in `BENCHMARK_LEVELS` on the index at `benchmark_compression`
+    pub fn benchmark_compression(&self) -> result::Result<()> {
+        let path = match &self.config.benchmark_compression {
+            Some(path) => path,
+            None => return Err(Error::msg("No index file to benchmark was given")),
+        };
+
+        let bytes = std::fs::read(path).map_err(RustfoilError::input_read(path))?;
+        let json = if bytes.starts_with(b"TINFOIL") {
+            decode_tinfoil_format(
+                bytes.as_slice(),
+                self.config.private_key.as_deref(),
+                self.config.rsa_padding,
+            )?
+        } else {
+            String::from_utf8(bytes)?
+        };
+
+        self.logger.log_info(
+            format!(
+                "Benchmarking {} of JSON from {}",
+                size::format_size(json.len() as u64),
+                path.display()
+            )
+            .as_str(),
+        )?;
+        self.logger.log_info(
+            format!(
+                "{:<11}  {:>12}  {:>6}  {:>8}",
+                "Compression", "Size", "Ratio", "Time"
+            )
+            .as_str(),
+        )?;
+
+        for row in compression::benchmark(json.as_str())? {
+            self.logger.log_info(
+                format!(
+                    "{:<11}  {:>12}  {:>5.1}%  {:>6}ms",
+                    row.name,
+                    size::format_size(row.size as u64),
+                    row.size as f64 / json.len().max(1) as f64 * 100.0,
+                    row.elapsed.as_millis()
+                )
+                .as_str(),
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// Decodes the index at `check` & logs every problem found in it
+    pub fn check_index(&self) -> result::Result<()> {
+        let path = match &self.config.check {
+            Some(path) => path,
+            None => return Err(Error::msg("No index file to check was given")),
+        };
+
+        let index = self.read_index(path)?;
+        let problems = index.problems();
+
+        for problem in &problems {
+            self.logger.log_error(problem.as_str())?;
+        }
+
+        if !problems.is_empty() {
+            return Err(Error::new(RustfoilError::CheckFailed(problems.len())));
+        }
+
+        self.logger.log_info(
+            format!(
+                "{} passed the check with {} files",
+                path.display(),
+                index.files.iter().flatten().count()
+            )
+            .as_str(),
+        )?;
+
+        Ok(())
+    }
+
+    /// Compares the indexes at `config.diff` and logs every added, removed & changed file
+    pub fn diff_indexes(&self) -> result::Result<IndexDiff> {
+        let (old_path, new_path) = match &self.config.diff {
+            Some(paths) => paths,
+            None => return Err(Error::msg("No index files to compare were given")),
+        };
+
+        let diff = self.read_index(old_path)?.diff(&self.read_index(new_path)?);
+
+        for file in &diff.added {
+            self.logger.log_info(
+                format!("Added {} ({})", file.url(), size::format_size(file.size())).as_str(),
+            )?;
+        }
+
+        for file in &diff.removed {
+            self.logger.log_info(
+                format!(
+                    "Removed {} ({})",
+                    file.url(),
+                    size::format_size(file.size())
+                )
+                .as_str(),
+            )?;
+        }
+
+        for (old, new) in &diff.changed {
+            self.logger.log_info(
+                format!(
+                    "Changed {} from {} to {}",
+                    new.url(),
+                    size::format_size(old.size()),
+                    size::format_size(new.size())
+                )
+                .as_str(),
+            )?;
+        }
+
+        self.logger.log_info(
+            format!(
+                "{} added, {} removed & {} changed files",
+                diff.added.len(),
+                diff.removed.len(),
+                diff.changed.len()
+            )
+            .as_str(),
+        )?;
+
+        Ok(diff)
+    }
+
+    /// Decodes & parses the index file at `path`, decrypting it with `private_key` if needed
+    fn read_index(&self, path: &Path) -> result::Result<Index> {
+        let json = decode_tinfoil_format(
+            std::fs::read(path)
+                .map_err(RustfoilError::input_read(path))?
+                .as_slice(),
+            self.config.private_key.as_deref(),
+            self.config.rsa_padding,
+        )?;
+
+        Ok(serde_json::from_str(json.as_str()).map_err(RustfoilError::invalid_json_file(path))?)
+    }
+
+    /// Runs the whole pipeline: scanning, generating & writing the index, sharing and uploading
+    pub fn run(mut self) -> result::Result<Report> {
+        self.validate_config()?;
+
+        self.init()?;
+
+        let (files, index_paths) = if self.config.per_folder {
+            self.index_per_folder()?
+        } else {
+            self.index_files(None)?
+        };
+
+        if self.config.share_files {
+            self.share_files(&files)?;
+        }
+
+        cancel::check()?;
+
+        let drive = DriveTarget { service: &self };
+        let s3 = match &self.config.s3_bucket {
+            Some(bucket) => Some(S3Target::new(
+                bucket,
+                self.config.s3_key.as_deref(),
+                &self
+                    .config
+                    .output_path
+                    .file_name()
+                    .unwrap_or_default()
+                    .to_string_lossy(),
+                self.config.s3_region.as_str(),
+                self.config.s3_endpoint.as_deref(),
+                Duration::from_secs(self.config.timeout),
+            )?),
+            None => None,
+        };
+
+        let mut targets: Vec<&dyn StorageTarget> = Vec::new();
+
+        if self.config.upload_my_drive || self.config.upload_folder_id.is_some() {
+            targets.push(&drive);
+        }
+
+        if let Some(s3) = &s3 {
+            targets.push(s3);
+        }
+
+        targets.extend(self.storage.iter().map(|target| target.as_ref()));
+
+        let mut uploaded = Vec::new();
+
+        for target in targets {
+            for path in &index_paths {
+                uploaded.push(target.upload(path.as_path())?);
+            }
+        }
+
+        self.finalize()?;
+
+        Ok(Report {
+            total_size: index::total_size(&files),
+            files,
+            index_paths,
+            uploaded,
+            elapsed: self.timer.elapsed(),
+        })
+    }
+
+    /// Writes an index for each folder, named by the folder ID, & returns all files & paths
+    fn index_per_folder(&mut self) -> result::Result<(Vec<ParsedFileInfo>, Vec<PathBuf>)> {
+        let folder_ids = self.config.folder_ids.to_owned();
+        let output_path = self.config.output_path.to_owned();
+        let report = self.config.report.to_owned();
+        let urls_only = self.config.urls_only.to_owned();
+
+        let mut files = Vec::new();
+        let mut index_paths = Vec::new();
+
+        for id in &folder_ids {
+            let suffix = parse_folder_id(id);
+
+            self.config.folder_ids = vec![id.to_owned()];
+            self.config.output_path = output_path.to_owned();
+            self.config.report = report
+                .as_ref()
+                .map(|path| RustfoilService::suffixed_path(path, suffix.as_str()));
+            self.config.urls_only = urls_only
+                .as_ref()
+                .map(|path| RustfoilService::suffixed_path(path, suffix.as_str()));
+
+            let (mut folder_files, mut paths) = self.index_files(Some(suffix.as_str()))?;
+            files.append(&mut folder_files);
+            index_paths.append(&mut paths);
+        }
+
+        self.config.folder_ids = folder_ids;
+        self.config.output_path = output_path;
+        self.config.report = report;
+        self.config.urls_only = urls_only;
+
+        Ok((files, index_paths))
+    }
+
+    /// Removes duplicates & applies the size & count caps, in this order
+    fn select_files(&self, mut files: Vec<ParsedFileInfo>) -> result::Result<Vec<ParsedFileInfo>> {
+        if let Some(strategy) = self.config.dedupe_strategy {
+            let scanned = files.len();
+            files = self.dedupe(files, strategy)?;
+
+            if files.len() < scanned {
+                self.logger.log_info(
+                    format!(
+                        "Removed {} duplicates by {}",
+                        scanned - files.len(),
+                        strategy
+                    )
+                    .as_str(),
+                )?;
+            }
+        }
+
+        if let Some(max_total_size) = self.config.max_total_size {
+            files = self.cap_total_size(files, max_total_size)?;
+        }
+
+        if let Some(max_files) = self.config.max_files {
+            if files.len() > max_files {
+                self.logger.log_info(
+                    format!(
+                        "Limited the index to the first {} files, omitting {}",
+                        max_files,
+                        files.len() - max_files
+                    )
+                    .as_str(),
+                )?;
+                files.truncate(max_files);
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Scans, generates & writes one index, `suffix` is appended to the names of its files
+    fn index_files(
+        &mut self,
+        suffix: Option<&str>,
+    ) -> result::Result<(Vec<ParsedFileInfo>, Vec<PathBuf>)> {
+        let files = if self.config.from_stdin {
+            self.read_file_list(std::io::stdin().lock())?
+        } else {
+            let mut files = self.scan_folder()?;
+            files.append(&mut self.scan_http()?);
+            files
+        };
+
+        let files = self.select_files(files)?;
+
+        if self.config.warn_duplicates {
+            self.warn_duplicates(&files)?;
+        }
+
+        self.logger.log_info(
+            format!(
+                "Indexing {} files totalling {}",
+                files.len(),
+                size::format_size(index::total_size(&files))
+            )
+            .as_str(),
+        )?;
+
+        let index = self.generate_index(files.to_owned())?;
+
+        if index.files.iter().flatten().next().is_none() {
+            self.logger
+                .log_warning("No files were found, check the folder IDs & filters")?;
+
+            if !self.config.allow_empty {
+                return Err(Error::new(RustfoilError::NoFiles));
+            }
+        }
+
+        if let Some(path) = &self.config.urls_only {
+            self.write_url_list(&index, path.as_path())?;
+        }
+
+        if self.config.list {
+            self.print_entries(&index)?;
+            return Ok((files, Vec::new()));
+        }
+
+        if let Some(output_dir) = &self.config.output_dir {
+            let count = index.files.iter().flatten().count();
+            let total_size = index.files.iter().flatten().map(|file| file.size()).sum();
+            let name = self.fill_placeholders(&self.config.name_pattern, count, total_size)?;
+
+            std::fs::create_dir_all(output_dir)?;
+            self.config.output_path = output_dir.join(name);
+        }
+
+        if let Some(suffix) = suffix {
+            self.config.output_path =
+                RustfoilService::suffixed_path(self.config.output_path.as_path(), suffix);
+        }
+
+        cancel::check()?;
+
+        let index_paths = if self.config.no_index {
+            Vec::new()
+        } else {
+            self.output_index(&index)?
+        };
+
+        if let Some(path) = &self.config.report {
+            self.write_report(&index, path.as_path())?;
+        }
+
+        Ok((files, index_paths))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn service(config: RustfoilConfig) -> RustfoilService {
+        RustfoilService::new(config)
+    }
+
+    fn info(name: &str) -> FileInfo {
+        FileInfo::new(
+            name.to_string(),
+            "1024".to_string(),
+            name.to_string(),
+            false,
+        )
+    }
+
+    fn parsed(id: &str, name: &str, size: &str) -> ParsedFileInfo {
+        ParsedFileInfo::new(
+            FileInfo::new(id.to_string(), size.to_string(), name.to_string(), false),
+            None,
+            false,
+            None,
+        )
+    }
+
+    fn urls(index: &Index) -> Vec<&str> {
+        index.files.iter().flatten().map(FileEntry::url).collect()
+    }
+
+    const CREDENTIALS: &str = r#"{"installed":{"client_id":"client","client_secret":"secret","auth_uri":"https://accounts.google.com/o/oauth2/auth","token_uri":"https://oauth2.googleapis.com/token","redirect_uris":["urn:ietf:wg:oauth:2.0:oob"]}}"#;
+    const TOKEN: &str = r#"{"tokens":[{"hash":1,"scopes":["https://www.googleapis.com/auth/drive"],"token":{"access_token":"access","refresh_token":"refresh","token_type":"Bearer","expires_in":null,"expires_in_timestamp":4102444800}}]}"#;
+
+    /// Empty directory for the files of one test
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rustfoil-service-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn raw_listing() -> Vec<FileInfo> {
+        let mut shortcut = info("Shortcut [0100000000010000].nsp");
+        shortcut.mime_type = Some("application/vnd.google-apps.shortcut".to_string());
+
+        let mut doc = info("Notes");
+        doc.mime_type = Some("application/vnd.google-apps.document".to_string());
+
+        let mut foreign = info("Foreign [0100000000020000].nsp");
+        foreign.owned_by_me = Some(false);
+
+        vec![
+            info("Game [0100000000010000][v0].nsp"),
+            info("Game without Title ID.nsp"),
+            info("readme.txt"),
+            shortcut,
+            doc,
+            foreign,
+        ]
+    }
+
+    #[test]
+    fn no_filter_keeps_the_raw_listing() {
+        let service = service(RustfoilConfig {
+            no_filter: true,
+            owner_only: true,
+            ..RustfoilConfig::default()
+        });
+        let (mut google_native, mut not_owned) = (0, 0);
+
+        let files = service.filter_listing(raw_listing(), None, &mut google_native, &mut not_owned);
+
+        assert_eq!(files.len(), raw_listing().len());
+        assert_eq!((google_native, not_owned), (0, 0));
+    }
+
+    #[test]
+    fn filters_drop_everything_but_indexable_files() {
+        let service = service(RustfoilConfig {
+            owner_only: true,
+            ..RustfoilConfig::default()
+        });
+        let (mut google_native, mut not_owned) = (0, 0);
+
+        let files = service.filter_listing(raw_listing(), None, &mut google_native, &mut not_owned);
+
+        assert_eq!(
+            files
+                .iter()
+                .map(|file| file.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Game [0100000000010000][v0].nsp"]
+        );
+        assert_eq!((google_native, not_owned), (1, 1));
+    }
+
+    fn list_good_folder(
+        id: &str,
+        _cache: &mut Option<ScanCache>,
+    ) -> std::result::Result<Vec<FileInfo>, RustfoilError> {
+        match id {
+            "good" => Ok(vec![info("Game [0100000000010000][v0].nsp")]),
+            _ => Err(RustfoilError::Drive("File not found".to_string())),
+        }
+    }
+
+    #[test]
+    fn failing_folder_is_skipped_with_continue_on_error() {
+        let service = service(RustfoilConfig {
+            folder_ids: vec!["good".to_string(), "bad".to_string()],
+            continue_on_error: true,
+            ..RustfoilConfig::default()
+        });
+
+        let (files, _, _) = service
+            .collect_folders(&mut None, &mut list_good_folder)
+            .unwrap();
+
+        assert_eq!(files.len(), 1);
+        assert_eq!(files[0].id, "Game [0100000000010000][v0].nsp");
+    }
+
+    #[test]
+    fn failing_folder_fails_the_scan() {
+        let service = service(RustfoilConfig {
+            folder_ids: vec!["good".to_string(), "bad".to_string()],
+            ..RustfoilConfig::default()
+        });
+
+        let error = service
+            .collect_folders(&mut None, &mut list_good_folder)
+            .unwrap_err();
+
+        assert_eq!(
+            format!("{:#}", error),
+            "Failed to scan folder bad: Google Drive request failed: File not found"
+        );
+        assert_eq!(crate::error::exit_code(&error), crate::error::EXIT_REMOTE);
+    }
+
+    #[test]
+    fn brace_style_title_id_pattern() {
+        let service = service(RustfoilConfig {
+            title_id_pattern: Some(r"\{([0-9A-Fa-f]{16})\}".to_string()),
+            ..RustfoilConfig::default()
+        });
+
+        assert_eq!(
+            service.title_id("Game {0100abcdef010000} (v0).nsp"),
+            Some("0100ABCDEF010000".to_string())
+        );
+        assert_eq!(service.title_id("Game [0100ABCDEF010000][v0].nsp"), None);
+    }
+
+    #[test]
+    fn title_id_pattern_needs_a_capture_group() {
+        assert!(RustfoilService::compile_title_id_regex(None)
+            .unwrap()
+            .is_none());
+        assert!(RustfoilService::compile_title_id_regex(Some(r"\{[0-9A-F]{16}\}")).is_err());
+        assert!(RustfoilService::compile_title_id_regex(Some(r"\{([0-9A-F]{16}")).is_err());
+    }
+
+    #[test]
+    fn share_results_are_aggregated_across_workers() {
+        let service = service(RustfoilConfig::default());
+        let files: Vec<ParsedFileInfo> = (0..20)
+            .map(|n| ParsedFileInfo::new(info(&format!("{}.nsp", n)), None, false, None))
+            .collect();
+        let pending: Vec<&ParsedFileInfo> = files.iter().collect();
+        let shares = AtomicUsize::new(0);
+
+        let (shared, failures) =
+            service.share_concurrently(&pending, vec![0, 1, 2, 3], 0, files.len(), &|_, file| {
+                shares.fetch_add(1, Ordering::SeqCst);
+
+                if file.name.starts_with('1') {
+                    return Err(RustfoilError::Drive("Rate limit exceeded".to_string()));
+                }
+
+                Ok(())
+            });
+
+        let shared: BTreeSet<&str> = shared.into_iter().collect();
+        let failed: BTreeSet<String> = failures.into_iter().map(|(name, _)| name).collect();
+
+        assert_eq!(shares.load(Ordering::SeqCst), files.len());
+        assert_eq!(shared.len() + failed.len(), files.len());
+        assert_eq!(failed.len(), 11);
+        assert!(failed.iter().all(|name| name.starts_with('1')));
+        assert!(!shared.contains("1.nsp") && shared.contains("2.nsp"));
+    }
+
+    #[test]
+    fn url_uses_the_prefix_and_keeps_the_name_fragment() {
+        let file = || vec![parsed("1a2b", "Game [0100000000010000][v0].nsp", "5")];
+
+        let index = service(RustfoilConfig::default())
+            .generate_index(file())
+            .unwrap();
+        assert_eq!(
+            urls(&index),
+            vec!["gdrive:1a2b#Game%20[0100000000010000][v0].nsp"]
+        );
+
+        let index = service(RustfoilConfig {
+            url_prefix: "proxy://drive/".to_string(),
+            ..RustfoilConfig::default()
+        })
+        .generate_index(file())
+        .unwrap();
+        assert_eq!(
+            urls(&index),
+            vec!["proxy://drive/1a2b#Game%20[0100000000010000][v0].nsp"]
+        );
+    }
+
+    #[test]
+    fn credentials_and_token_are_read_from_the_environment() {
+        std::env::set_var("RUSTFOIL_TEST_ENV_CREDENTIALS", CREDENTIALS);
+        std::env::set_var("RUSTFOIL_TEST_ENV_TOKEN", TOKEN);
+
+        let service = service(RustfoilConfig {
+            credentials: PathBuf::from("missing/credentials.json"),
+            token: PathBuf::from("missing/token.json"),
+            credentials_env: "RUSTFOIL_TEST_ENV_CREDENTIALS".to_string(),
+            token_env: "RUSTFOIL_TEST_ENV_TOKEN".to_string(),
+            ..RustfoilConfig::default()
+        });
+
+        assert!(service.ensure_credentials().is_ok());
+        assert_eq!(service.read_credentials().unwrap().client_id, "client");
+
+        let token = yup_oauth2::TokenStorage::get(
+            &service.read_token_store().unwrap(),
+            1,
+            &vec!["https://www.googleapis.com/auth/drive"],
+        )
+        .unwrap();
+        assert_eq!(
+            token.map(|token| token.access_token),
+            Some("access".to_string())
+        );
+    }
+
+    #[test]
+    fn missing_credentials_file_and_empty_env_are_refused() {
+        std::env::set_var("RUSTFOIL_TEST_EMPTY_CREDENTIALS", " ");
+
+        let service = service(RustfoilConfig {
+            credentials: PathBuf::from("missing/credentials.json"),
+            credentials_env: "RUSTFOIL_TEST_EMPTY_CREDENTIALS".to_string(),
+            ..RustfoilConfig::default()
+        });
+
+        assert!(matches!(
+            service.ensure_credentials(),
+            Err(RustfoilError::CredentialsMissing)
+        ));
+    }
+
+    #[test]
+    fn size_filter_keeps_both_boundaries() {
+        let service = service(RustfoilConfig {
+            min_size: Some(100),
+            max_size: Some(200),
+            ..RustfoilConfig::default()
+        });
+        let file = |size: &str| parsed("id", "Game [0100000000010000].nsp", size);
+
+        assert!(!service.keep_file(&file("99")));
+        assert!(service.keep_file(&file("100")));
+        assert!(service.keep_file(&file("200")));
+        assert!(!service.keep_file(&file("201")));
+    }
+
+    #[test]
+    fn failed_write_reports_the_path_and_leaves_no_temp_file() {
+        let dir = temp_dir("write-failure");
+        // Renaming a file over a directory fails after the temp file was written
+        let path = dir.join("index.tfl");
+        std::fs::create_dir(&path).unwrap();
+
+        let error = service(RustfoilConfig::default())
+            .write_atomically(&path, b"index")
+            .unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            format!("Couldn't write output file {}", path.display())
+        );
+        assert_eq!(crate::error::exit_code(&error), crate::error::EXIT_IO);
+        assert!(path.is_dir());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn successful_write_replaces_the_file() {
+        let dir = temp_dir("write");
+        let path = dir.join("index.tfl");
+        std::fs::write(&path, b"old").unwrap();
+
+        service(RustfoilConfig::default())
+            .write_atomically(&path, b"new")
+            .unwrap();
+
+        assert_eq!(std::fs::read(&path).unwrap(), b"new");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn allowlist_keeps_only_listed_title_ids() {
+        let dir = temp_dir("allowlist");
+        let allowlist = dir.join("allowlist.txt");
+        std::fs::write(
+            &allowlist,
+            "# Curated\n0100000000010000\n\n0100abcdef020000\n",
+        )
+        .unwrap();
+
+        let mut service = service(RustfoilConfig {
+            title_id_allowlist: Some(allowlist),
+            ..RustfoilConfig::default()
+        });
+        service.init().unwrap();
+
+        let keeps = |name: &str| service.keep_file(&parsed("id", name, "1"));
+
+        assert!(keeps("Game [0100000000010000][v0].nsp"));
+        assert!(keeps("Other [0100ABCDEF020000][v0].nsp"));
+        assert!(!keeps("Unlisted [0100000000030000][v0].nsp"));
+        assert!(!keeps("No Title ID.nsp"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn allowlist_with_an_invalid_title_id_is_refused() {
+        let dir = temp_dir("invalid-allowlist");
+        let allowlist = dir.join("allowlist.txt");
+        std::fs::write(&allowlist, "0100000000010000\n0100\n").unwrap();
+
+        let error = RustfoilService::read_title_ids(&allowlist).unwrap_err();
+
+        assert_eq!(
+            error.to_string(),
+            "Invalid Title ID \"0100\", expected 16 hexadecimal characters"
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn bad_size_is_skipped_among_good_ones() {
+        let files = || {
+            vec![
+                parsed("1", "A [0100000000010000].nsp", "10"),
+                parsed("2", "B [0100000000020000].nsp", ""),
+                parsed("3", "C [0100000000030000].nsp", "30"),
+            ]
+        };
+
+        let index = service(RustfoilConfig::default())
+            .generate_index(files())
+            .unwrap();
+        let sizes: Vec<u64> = index.files.iter().flatten().map(FileEntry::size).collect();
+        assert_eq!(sizes, vec![10, 30]);
+
+        let error = service(RustfoilConfig {
+            strict_size: true,
+            ..RustfoilConfig::default()
+        })
+        .generate_index(files())
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Invalid size \"\" of file B [0100000000020000].nsp"
+        );
+    }
+
+    #[test]
+    fn owner_only_skips_files_owned_by_others() {
+        let listing = || {
+            [Some(true), None, Some(false)]
+                .iter()
+                .enumerate()
+                .map(|(n, owned_by_me)| FileInfo {
+                    owned_by_me: *owned_by_me,
+                    ..info(&format!("Game{} [0100000000010000].nsp", n))
+                })
+                .collect::<Vec<_>>()
+        };
+        let (mut google_native, mut not_owned) = (0, 0);
+
+        let owner_only = service(RustfoilConfig {
+            owner_only: true,
+            ..RustfoilConfig::default()
+        });
+        let files = owner_only.filter_listing(listing(), None, &mut google_native, &mut not_owned);
+        assert_eq!(
+            files
+                .iter()
+                .map(|file| file.name.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "Game0 [0100000000010000].nsp",
+                "Game1 [0100000000010000].nsp"
+            ]
+        );
+        assert_eq!(not_owned, 1);
+
+        let everyone = service(RustfoilConfig::default());
+        let files = everyone.filter_listing(listing(), None, &mut google_native, &mut not_owned);
+        assert_eq!(files.len(), 3);
+    }
+
+    #[test]
+    fn report_has_a_row_per_file() {
+        let dir = temp_dir("report");
+        let path = dir.join("report.txt");
+        let service = service(RustfoilConfig::default());
+        let index = service
+            .generate_index(vec![
+                parsed("1", "A [0100000000010000][v0].nsp", "1024"),
+                parsed("2", "B [0100000000020000][v0].nsz", "2048"),
+                parsed("3", "C [0100000000030000][v0].xci", "4096"),
+            ])
+            .unwrap();
+
+        service.write_report(&index, &path).unwrap();
+
+        let report = std::fs::read_to_string(&path).unwrap();
+        let rows: Vec<&str> = report.lines().skip(1).collect();
+        assert_eq!(rows.len(), 3);
+        assert!(report.starts_with("Name"));
+        assert!(rows[1].starts_with("B [0100000000020000][v0].nsz"));
+        assert!(rows[1].ends_with("0100000000020000"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn success_message_placeholders_are_filled() {
+        let index = service(RustfoilConfig {
+            success: Some("Loaded {count} games ({size}), {unknown} stays".to_string()),
+            theme_error: Some("{count} files".to_string()),
+            ..RustfoilConfig::default()
+        })
+        .generate_index(vec![
+            parsed("1", "A [0100000000010000].nsp", "1024"),
+            parsed("2", "B [0100000000020000].nsp", "1024"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            index.success.as_deref(),
+            Some("Loaded 2 games (2.00 KiB), {unknown} stays")
+        );
+        assert_eq!(index.theme_error.as_deref(), Some("2 files"));
+    }
+
+    #[test]
+    fn existing_output_is_only_replaced_with_overwrite() {
+        let dir = temp_dir("overwrite");
+        let path = dir.join("index.tfl");
+        std::fs::write(&path, b"hand-edited").unwrap();
+        let index = Index::new();
+
+        let error = service(RustfoilConfig::default())
+            .write_index(&index, &path)
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RustfoilError>(),
+            Some(RustfoilError::OutputExists(existing)) if *existing == path
+        ));
+        assert_eq!(std::fs::read(&path).unwrap(), b"hand-edited");
+
+        service(RustfoilConfig {
+            overwrite: true,
+            ..RustfoilConfig::default()
+        })
+        .write_index(&index, &path)
+        .unwrap();
+        assert!(std::fs::read(&path).unwrap().starts_with(b"TINFOIL"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn malformed_one_fichier_key_warns_unless_strict() {
+        let keys = Some(vec![
+            "AbCdEf0123456789".to_string(),
+            " user:password ".to_string(),
+        ]);
+
+        let index = service(RustfoilConfig {
+            one_fichier_keys: keys.to_owned(),
+            ..RustfoilConfig::default()
+        })
+        .generate_index(Vec::new())
+        .unwrap();
+        assert_eq!(
+            index.one_fichier_keys,
+            Some(vec![
+                "AbCdEf0123456789".to_string(),
+                "user:password".to_string()
+            ])
+        );
+
+        assert!(service(RustfoilConfig {
+            one_fichier_keys: keys,
+            strict_keys: true,
+            ..RustfoilConfig::default()
+        })
+        .generate_index(Vec::new())
+        .is_err());
+    }
+
+    #[test]
+    fn max_files_truncates_to_the_first_files() {
+        let files: Vec<ParsedFileInfo> = (0..5)
+            .map(|n| parsed(&n.to_string(), &format!("{}.nsp", n), "1"))
+            .collect();
+
+        let selected = service(RustfoilConfig {
+            max_files: Some(3),
+            ..RustfoilConfig::default()
+        })
+        .select_files(files.to_owned())
+        .unwrap();
+        assert_eq!(
+            selected
+                .iter()
+                .map(|file| file.id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["0", "1", "2"]
+        );
+
+        let selected = service(RustfoilConfig {
+            max_files: Some(10),
+            ..RustfoilConfig::default()
+        })
+        .select_files(files)
+        .unwrap();
+        assert_eq!(selected.len(), 5);
+    }
+
+    fn testdata(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join(name)
+    }
+
+    fn check(path: &Path, private_key: Option<PathBuf>) -> result::Result<()> {
+        service(RustfoilConfig {
+            check: Some(path.to_owned()),
+            private_key,
+            ..RustfoilConfig::default()
+        })
+        .check_index()
+    }
+
+    #[test]
+    fn check_passes_a_valid_index() {
+        let dir = temp_dir("check-valid");
+        let path = dir.join("index.tfl");
+        let files = || vec![parsed("1", "A [0100000000010000].nsp", "10")];
+
+        let plain = service(RustfoilConfig::default());
+        plain
+            .write_index(&plain.generate_index(files()).unwrap(), &path)
+            .unwrap();
+        check(&path, None).unwrap();
+
+        let encrypted = service(RustfoilConfig {
+            public_key: Some(testdata("test_key.pub.pem")),
+            overwrite: true,
+            ..RustfoilConfig::default()
+        });
+        encrypted
+            .write_index(&encrypted.generate_index(files()).unwrap(), &path)
+            .unwrap();
+        check(&path, Some(testdata("test_key.pem"))).unwrap();
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn check_fails_a_corrupted_index() {
+        let dir = temp_dir("check-corrupted");
+        let path = dir.join("index.tfl");
+        let service = service(RustfoilConfig::default());
+        let index = service
+            .generate_index(vec![
+                parsed("1", "A [0100000000010000].nsp", "10"),
+                parsed("2", "B [0100000000020000].nsp", "20"),
+            ])
+            .unwrap();
+        service.write_index(&index, &path).unwrap();
+
+        let mut bytes = std::fs::read(&path).unwrap();
+        bytes.truncate(bytes.len() - 20);
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(check(&path, None).is_err());
+
+        std::fs::write(&path, b"not an index").unwrap();
+        assert_eq!(
+            check(&path, None).unwrap_err().to_string(),
+            "Invalid Tinfoil file: missing TINFOIL header"
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn check_reports_broken_entries() {
+        let dir = temp_dir("check-entries");
+        let path = dir.join("index.tfl");
+        let service = service(RustfoilConfig::default());
+        let mut index = Index::new();
+        index.files = Some(vec![
+            FileEntry::new("gdrive:1#A.nsp".to_string(), 10),
+            FileEntry::new("gdrive:1#A.nsp".to_string(), 10),
+            FileEntry::new("gdrive:2#B.nsp".to_string(), 0),
+        ]);
+        service.write_index(&index, &path).unwrap();
+
+        let error = check(&path, None).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RustfoilError>(),
+            Some(RustfoilError::CheckFailed(2))
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn name_pattern_placeholders_are_expanded() {
+        let service = service(RustfoilConfig::default());
+        let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+
+        assert_eq!(
+            service
+                .fill_placeholders("index-{date}-{count}-{other}.tfl", 42, 0)
+                .unwrap(),
+            format!("index-{}-42-{{other}}.tfl", today)
+        );
+    }
+
+    #[test]
+    fn cached_shares_skip_drive_entirely() {
+        let dir = temp_dir("share-cache");
+        let path = dir.join("shared.json");
+        let mut cache = ShareCache::default();
+        cache.insert("1");
+        cache.save(&path).unwrap();
+
+        // No Drive service exists, so sharing anything but cache hits would fail
+        service(RustfoilConfig {
+            share_cache: Some(path.to_owned()),
+            ..RustfoilConfig::default()
+        })
+        .share_files(&[parsed("1", "A [0100000000010000].nsp", "1")])
+        .unwrap();
+
+        assert!(ShareCache::load(&path).unwrap().contains("1"));
+
+        // A refreshed cache misses, so the file is shared again & needs the missing credentials
+        let error = service(RustfoilConfig {
+            share_cache: Some(path.to_owned()),
+            refresh_share_cache: true,
+            credentials: dir.join("credentials.json"),
+            credentials_env: "RUSTFOIL_TEST_UNSET_CREDENTIALS".to_string(),
+            ..RustfoilConfig::default()
+        })
+        .share_files(&[parsed("1", "A [0100000000010000].nsp", "1")])
+        .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<RustfoilError>(),
+            Some(RustfoilError::InputRead { .. })
+        ));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn shares_are_spread_over_the_workers_once_each() {
+        let service = service(RustfoilConfig {
+            share_concurrency: 4,
+            ..RustfoilConfig::default()
+        });
+        assert_eq!(service.share_workers(0), 1);
+        assert_eq!(service.share_workers(2), 2);
+        assert_eq!(service.share_workers(250), 4);
+
+        let files: Vec<ParsedFileInfo> = (0..250)
+            .map(|n| parsed(&n.to_string(), &format!("{}.nsp", n), "1"))
+            .collect();
+        let pending: Vec<&ParsedFileInfo> = files.iter().collect();
+        let workers: Vec<Mutex<Vec<String>>> = (0..service.share_workers(pending.len()))
+            .map(|_| Mutex::new(Vec::new()))
+            .collect();
+
+        let (shared, failures) = service.share_concurrently(
+            &pending,
+            workers.iter().collect(),
+            0,
+            files.len(),
+            &|worker, file| {
+                worker.lock().unwrap().push(file.id.to_owned());
+                Ok(())
+            },
+        );
+
+        let mut handed_out: Vec<String> = workers
+            .into_iter()
+            .flat_map(|worker| worker.into_inner().unwrap())
+            .collect();
+        handed_out.sort();
+        assert_eq!(handed_out.len(), files.len());
+        handed_out.dedup();
+        assert_eq!(handed_out.len(), files.len());
+        assert_eq!(shared.len(), files.len());
+        assert!(failures.is_empty());
+    }
+
+    #[test]
+    fn warns_above_the_threshold() {
+        let unsplit = service(RustfoilConfig {
+            warn_threshold: 2,
+            ..RustfoilConfig::default()
+        });
+        assert_eq!(unsplit.size_warning(2), None);
+        assert_eq!(
+            unsplit.size_warning(3).unwrap(),
+            "The index has 3 files, some Tinfoil builds struggle with more than 2, consider using --split-size"
+        );
+
+        let split = service(RustfoilConfig {
+            warn_threshold: 2,
+            split_size: Some(1024),
+            ..RustfoilConfig::default()
+        });
+        assert_eq!(split.size_warning(3), None);
+    }
+
+    /// Records the emitted progress events
+    struct CapturedProgress(Arc<Mutex<Vec<String>>>);
+
+    impl ProgressSink for CapturedProgress {
+        fn on_file_found(&self, file: &ParsedFileInfo) {
+            self.0.lock().unwrap().push(format!("found {}", file.id));
+        }
+
+        fn on_scan_failed(&self) {
+            self.0.lock().unwrap().push("scan failed".to_string());
+        }
+
+        fn on_share_progress(&self, done: usize, total: usize) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("shared {}/{}", done, total));
+        }
+    }
+
+    #[test]
+    fn progress_events_are_emitted() {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let service = service(RustfoilConfig {
+            folder_ids: vec!["good".to_string(), "bad".to_string()],
+            ..RustfoilConfig::default()
+        })
+        .with_progress(Box::new(CapturedProgress(events.clone())));
+
+        assert!(service
+            .collect_folders(&mut None, &mut list_good_folder)
+            .is_err());
+
+        let file = parsed("1a", "Game [0100000000010000][v0].nsp", "1");
+        service.share_concurrently(&[&file], vec![()], 1, 2, &|_, _| Ok(()));
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                "found Game [0100000000010000][v0].nsp",
+                "scan failed",
+                "shared 2/2"
+            ]
+        );
+    }
+
+    #[test]
+    fn url_prefix_is_mapped_per_extension() {
+        let service = service(RustfoilConfig {
+            url_map: vec![("nsz".to_string(), "myproxy:".to_string())],
+            ..RustfoilConfig::default()
+        });
+
+        let index = service
+            .generate_index(vec![
+                parsed("1a", "Game [0100000000010000][v0].NSZ", "5"),
+                parsed("2b", "Game [0100000000010800][v65536].nsp", "5"),
+            ])
+            .unwrap();
+
+        assert_eq!(
+            urls(&index),
+            vec![
+                "myproxy:1a#Game%20[0100000000010000][v0].NSZ",
+                "gdrive:2b#Game%20[0100000000010800][v65536].nsp"
+            ]
+        );
+    }
+
+    #[test]
+    fn deterministic_runs_match() {
+        let config = || RustfoilConfig {
+            deterministic: true,
+            public_key: Some(testdata("test_key.pub.pem")),
+            aes_key: Some("000102030405060708090a0b0c0d0e0f".to_string()),
+            ..RustfoilConfig::default()
+        };
+        let files = vec![
+            parsed("2b", "Game [0100000000010800][v65536].nsp", "5"),
+            parsed("1a", "Game [0100000000010000][v0].nsp", "5"),
+        ];
+
+        let run = |files: Vec<ParsedFileInfo>| {
+            let mut service = service(config());
+            service.validate_config().unwrap();
+            service.init().unwrap();
+            let index = service.generate_index(files).unwrap();
+            service.encode_index(&index).unwrap()
+        };
+
+        let first = run(files.clone());
+        let second = run(files.into_iter().rev().collect());
+        assert_eq!(first, second);
+
+        let error = service(RustfoilConfig {
+            aes_key: None,
+            ..config()
+        })
+        .validate_config()
+        .unwrap_err();
+        assert_eq!(
+            crate::error::exit_code(&error),
+            crate::error::EXIT_VALIDATION
+        );
+    }
+
+    #[test]
+    fn denylist_takes_precedence_over_allowlist() {
+        let dir = temp_dir("denylist");
+        let allowlist = dir.join("allowlist.txt");
+        let denylist = dir.join("denylist.txt");
+        std::fs::write(&allowlist, "0100000000010000\n0100000000020000\n").unwrap();
+        std::fs::write(&denylist, "0100000000020000\n0100000000030000\n").unwrap();
+
+        let mut service = service(RustfoilConfig {
+            title_id_allowlist: Some(allowlist),
+            title_id_denylist: Some(denylist.to_owned()),
+            ..RustfoilConfig::default()
+        });
+        service.init().unwrap();
+
+        let keeps = |name: &str| service.keep_file(&parsed("id", name, "1"));
+
+        assert!(keeps("Allowed [0100000000010000][v0].nsp"));
+        assert!(!keeps("Both [0100000000020000][v0].nsp"));
+        assert!(!keeps("Denied [0100000000030000][v0].nsp"));
+
+        // Files without a Title ID are unaffected by the denylist
+        let mut service = self::service(RustfoilConfig {
+            title_id_denylist: Some(denylist),
+            add_nsw_files_without_title_id: true,
+            ..RustfoilConfig::default()
+        });
+        service.init().unwrap();
+
+        let keeps = |name: &str| service.keep_file(&parsed("id", name, "1"));
+
+        assert!(keeps("Other [0100000000010000][v0].nsp"));
+        assert!(keeps("No Title ID.nsp"));
+        assert!(!keeps("Denied [0100000000030000][v0].nsp"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn since_keeps_files_modified_at_or_after_the_date() {
+        let since = chrono::DateTime::parse_from_rfc3339("2024-03-01T00:00:00Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let file = |modified_time: Option<&str>| ParsedFileInfo {
+            modified_time: modified_time.map(str::to_string),
+            ..parsed("id", "Game [0100000000010000][v0].nsp", "1")
+        };
+
+        let service = service(RustfoilConfig {
+            since: Some(since),
+            ..RustfoilConfig::default()
+        });
+
+        assert!(service.keep_file(&file(Some("2024-03-01T00:00:00Z"))));
+        assert!(service.keep_file(&file(Some("2024-03-01T00:00:00.001Z"))));
+        // Same instant in another time zone
+        assert!(service.keep_file(&file(Some("2024-03-01T01:00:00+01:00"))));
+        assert!(!service.keep_file(&file(Some("2024-02-29T23:59:59.999Z"))));
+        assert!(!service.keep_file(&file(None)));
+        assert!(!service.keep_file(&file(Some("yesterday"))));
+
+        let service = self::service(RustfoilConfig {
+            since: Some(since),
+            include_unknown_mtime: true,
+            ..RustfoilConfig::default()
+        });
+
+        assert!(service.keep_file(&file(None)));
+        assert!(!service.keep_file(&file(Some("2024-02-29T23:59:59Z"))));
+    }
+
+    #[test]
+    fn empty_scan_is_refused_unless_allowed() {
+        let dir = temp_dir("empty");
+        let output_path = dir.join("index.tfl");
+
+        let error = service(RustfoilConfig {
+            output_path: output_path.to_owned(),
+            ..RustfoilConfig::default()
+        })
+        .index_files(None)
+        .unwrap_err();
+
+        assert!(matches!(
+            error.downcast_ref::<RustfoilError>(),
+            Some(RustfoilError::NoFiles)
+        ));
+        assert_eq!(crate::error::exit_code(&error), crate::error::EXIT_EMPTY);
+        assert!(!output_path.exists());
+
+        let (files, paths) = service(RustfoilConfig {
+            output_path: output_path.to_owned(),
+            allow_empty: true,
+            ..RustfoilConfig::default()
+        })
+        .index_files(None)
+        .unwrap();
+
+        assert!(files.is_empty());
+        assert_eq!(paths, vec![output_path.to_owned()]);
+        assert!(output_path.is_file());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn exposes_the_compiled_title_id_regex() {
+        let pattern = r"\{([0-9A-Fa-f]{16})\}";
+        let service = service(RustfoilConfig {
+            title_id_pattern: Some(pattern.to_string()),
+            ..RustfoilConfig::default()
+        });
+
+        assert_eq!(service.title_id_regex().unwrap().unwrap().as_str(), pattern);
+        assert!(self::service(RustfoilConfig::default())
+            .title_id_regex()
+            .unwrap()
+            .is_none());
+
+        // An invalid pattern fails validation & any run skipping it, instead of using the style
+        let invalid = self::service(RustfoilConfig {
+            title_id_pattern: Some("([0-9A-F]{16}".to_string()),
+            ..RustfoilConfig::default()
+        });
+        for error in [
+            invalid.title_id_regex().unwrap_err(),
+            invalid.validate_config().unwrap_err(),
+            invalid
+                .generate_index(vec![parsed("1a", "Game [0100000000010000][v0].nsp", "5")])
+                .err()
+                .unwrap(),
+            invalid.scan_http().unwrap_err(),
+            invalid
+                .read_file_list("https://example.com/a.nsp\t5\n".as_bytes())
+                .unwrap_err(),
+        ] {
+            assert!(matches!(
+                error.downcast_ref::<RustfoilError>(),
+                Some(RustfoilError::InvalidTitleIdPattern(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn index_is_written_into_a_buffer() {
+        let service = service(RustfoilConfig {
+            output_path: PathBuf::from("-"),
+            compression: CompressionFlag::Zstd,
+            ..RustfoilConfig::default()
+        });
+        assert!(service.writes_to_stdout());
+
+        let index = service
+            .generate_index(vec![parsed("1a", "Game [0100000000010000][v0].nsp", "5")])
+            .unwrap();
+        let mut buffer = Vec::new();
+        service.write_index_to(&index, &mut buffer).unwrap();
+
+        assert!(buffer.starts_with(b"TINFOIL"));
+        let json = decode_tinfoil_format(&buffer, None, service.config.rsa_padding).unwrap();
+        assert_eq!(json, index.to_json(service.config.schema).unwrap());
+
+        assert!(!self::service(RustfoilConfig {
+            output_path: PathBuf::from("-"),
+            output_dir: Some(PathBuf::from("out")),
+            ..RustfoilConfig::default()
+        })
+        .writes_to_stdout());
+    }
+
+    #[test]
+    fn every_folder_gets_its_own_index() {
+        let (base_url, server) = crate::gdrive::tests::mock_drive(vec![
+            "{}",
+            r#"{"files":[{"id":"1a","name":"Game [0100000000010000][v0].nsp","size":"5"}]}"#,
+            "{}",
+            r#"{"files":[{"id":"2b","name":"Other [0100000000020000][v0].nsp","size":"7"}]}"#,
+        ]);
+        let dir = temp_dir("per-folder");
+
+        let mut service = service(RustfoilConfig {
+            folder_ids: vec![
+                "folderA".to_string(),
+                "https://drive.google.com/drive/folders/folderB".to_string(),
+            ],
+            per_folder: true,
+            no_recursion: true,
+            output_path: dir.join("index.tfl"),
+            ..RustfoilConfig::default()
+        });
+        service.gdrive = Some(crate::gdrive::tests::gdrive(base_url, Arc::default()));
+
+        let (files, paths) = service.index_per_folder().unwrap();
+        assert_eq!(server.join().unwrap().len(), 4);
+
+        assert_eq!(files.len(), 2);
+        assert_eq!(
+            paths,
+            vec![dir.join("index_folderA.tfl"), dir.join("index_folderB.tfl")]
+        );
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+        assert_eq!(service.config.output_path, dir.join("index.tfl"));
+
+        let part = service.read_index(paths[1].as_path()).unwrap();
+        assert_eq!(
+            part.files.unwrap()[0].url(),
+            "gdrive:2b#Other%20[0100000000020000][v0].nsp"
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn conflicting_options_are_refused() {
+        let conflicts: Vec<(RustfoilConfig, &str)> = vec![
+            (
+                RustfoilConfig {
+                    share_index: true,
+                    ..RustfoilConfig::default()
+                },
+                "--share-index only shares an index uploaded via --upload-my-drive or --upload-folder-id",
+            ),
+            (
+                RustfoilConfig {
+                    upload_my_drive: true,
+                    upload_folder_id: Some("folder".to_string()),
+                    ..RustfoilConfig::default()
+                },
+                "--upload-my-drive & --upload-folder-id can't be combined, pick one destination",
+            ),
+            (
+                RustfoilConfig {
+                    resume_upload: true,
+                    ..RustfoilConfig::default()
+                },
+                "--resume-upload needs --upload-my-drive or --upload-folder-id",
+            ),
+            (
+                RustfoilConfig {
+                    s3_key: Some("index.tfl".to_string()),
+                    ..RustfoilConfig::default()
+                },
+                "--s3-key & --s3-endpoint need --s3-bucket",
+            ),
+            (
+                RustfoilConfig {
+                    split_base_url: Some("https://example.com/".to_string()),
+                    ..RustfoilConfig::default()
+                },
+                "--split-base-url needs --split-size",
+            ),
+            (
+                RustfoilConfig {
+                    no_index: true,
+                    ..RustfoilConfig::default()
+                },
+                "--no-index needs --urls-only, otherwise nothing would be written",
+            ),
+            (
+                RustfoilConfig {
+                    per_folder: true,
+                    from_stdin: true,
+                    ..RustfoilConfig::default()
+                },
+                "--per-folder only indexes Drive folders, it can't be combined with --from-stdin",
+            ),
+            (
+                RustfoilConfig {
+                    list: true,
+                    split_size: Some(1024),
+                    ..RustfoilConfig::default()
+                },
+                "--split-size needs an index file, it can't be combined with --list",
+            ),
+        ];
+
+        for (config, message) in conflicts {
+            let error = service(config).validate_config().unwrap_err();
+
+            assert_eq!(error.to_string(), message);
+            assert_eq!(
+                crate::error::exit_code(&error),
+                crate::error::EXIT_VALIDATION
+            );
+        }
+
+        service(RustfoilConfig::default())
+            .validate_config()
+            .unwrap();
+    }
+
+    #[test]
+    fn normalized_extension_only_changes_the_entry_name() {
+        let service = service(RustfoilConfig {
+            normalize_extensions: true,
+            ..RustfoilConfig::default()
+        });
+        let file = parsed("1a", "GAME [0100000000010000][v0].NSP", "5");
+
+        let index = service.generate_index(vec![file.to_owned()]).unwrap();
+
+        assert_eq!(
+            urls(&index),
+            vec!["gdrive:1a#GAME%20[0100000000010000][v0].nsp"]
+        );
+        assert_eq!(file.name, "GAME [0100000000010000][v0].NSP");
+        assert_eq!(file.id, "1a");
+    }
+
+    #[test]
+    fn cancelling_stops_the_scan_between_folders() {
+        const TEST: &str = "service::tests::cancelling_stops_the_scan_between_folders";
+
+        // The cancellation flag is global, so it's only set in a process running just this test
+        if std::env::var_os("RUSTFOIL_TEST_CANCEL").is_none() {
+            let output = std::process::Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", TEST, "--test-threads=1"])
+                .env("RUSTFOIL_TEST_CANCEL", "1")
+                .output()
+                .unwrap();
+            assert!(output.status.success());
+            assert!(String::from_utf8_lossy(&output.stdout).contains("1 passed"));
+            return;
+        }
+
+        let service = service(RustfoilConfig {
+            folder_ids: vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            ..RustfoilConfig::default()
+        });
+        let mut listed = Vec::new();
+
+        let error = service
+            .collect_folders(&mut None, &mut |id, _| {
+                listed.push(id.to_string());
+                cancel::cancel();
+                Ok(Vec::new())
+            })
+            .unwrap_err();
+
+        assert_eq!(listed, vec!["a"]);
+        assert!(matches!(
+            error.downcast_ref::<RustfoilError>(),
+            Some(RustfoilError::Cancelled)
+        ));
+        assert_eq!(
+            crate::error::exit_code(&error),
+            crate::error::EXIT_INTERRUPTED
+        );
+    }
+
+    #[test]
+    fn estimate_message_flags_large_folders() {
+        let estimate = |more: bool| FolderEstimate {
+            folder_id: "folder".to_string(),
+            files: 998,
+            folders: 2,
+            more,
+            incomplete: false,
+        };
+
+        assert_eq!(
+            RustfoilService::estimate_message(&estimate(false)),
+            "Folder folder has 998 files & 2 subfolders"
+        );
+        assert_eq!(
+            RustfoilService::estimate_message(&estimate(true)),
+            "Folder folder has at least 998 files & 2 subfolders, it's a large folder with more than 1000 entries"
+        );
+    }
+
+    #[test]
+    fn folders_are_scanned_with_their_account() {
+        let (default_url, default_server) = crate::gdrive::tests::mock_drive(vec![
+            "{}",
+            r#"{"files":[{"id":"1a","name":"Game [0100000000010000][v0].nsp","size":"5"}]}"#,
+        ]);
+        let (work_url, work_server) = crate::gdrive::tests::mock_drive(vec![
+            "{}",
+            r#"{"files":[{"id":"2b","name":"Other [0100000000020000][v0].nsp","size":"7"}]}"#,
+        ]);
+
+        let mut service = service(RustfoilConfig {
+            folder_ids: vec![
+                "personal".to_string(),
+                "https://drive.google.com/drive/folders/shared".to_string(),
+            ],
+            accounts: vec![Account {
+                name: "work".to_string(),
+                credentials: PathBuf::from("work.json"),
+                token: PathBuf::from("work-token.json"),
+            }],
+            folder_accounts: vec![("shared".to_string(), "work".to_string())],
+            no_recursion: true,
+            ..RustfoilConfig::default()
+        });
+        assert_eq!(service.folder_account("personal").map(|a| &a.name), None);
+        assert_eq!(
+            service
+                .folder_account("https://drive.google.com/drive/folders/shared")
+                .map(|a| a.name.as_str()),
+            Some("work")
+        );
+
+        service.gdrive = Some(crate::gdrive::tests::gdrive(default_url, Arc::default()));
+        service.accounts.insert(
+            "work".to_string(),
+            crate::gdrive::tests::gdrive(work_url, Arc::default()),
+        );
+
+        let files = service.scan_folder().unwrap();
+        let default_requests = default_server.join().unwrap();
+        let work_requests = work_server.join().unwrap();
+
+        assert!(default_requests[1].contains("%22personal%22+in+parents"));
+        assert!(work_requests[1].contains("%22shared%22+in+parents"));
+        assert_eq!(
+            files
+                .iter()
+                .map(|file| (file.id.as_str(), file.account.as_deref()))
+                .collect::<Vec<_>>(),
+            vec![("1a", None), ("2b", Some("work"))]
+        );
+    }
+
+    #[test]
+    fn list_writes_one_json_object_per_entry() {
+        let service = service(RustfoilConfig {
+            list: true,
+            ..RustfoilConfig::default()
+        });
+        let index = service
+            .generate_index(vec![
+                parsed("1a", "Game [0100000000010000][v0].nsp", "5"),
+                parsed("2b", "Other [0100000000020000][v0].nsz", "7"),
+            ])
+            .unwrap();
+
+        let mut buffer = Vec::new();
+        RustfoilService::write_entries(&index, &mut buffer).unwrap();
+        let lines = String::from_utf8(buffer).unwrap();
+        let entries = lines
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+
+        assert_eq!(entries.len(), 2);
+        for (entry, (url, size)) in entries.iter().zip(urls(&index).into_iter().zip([5, 7])) {
+            assert_eq!(entry["url"], url);
+            assert_eq!(entry["size"], size);
+            assert_eq!(entry.as_object().unwrap().len(), 2);
+        }
+    }
+
+    #[test]
+    fn metadata_is_embedded_only_when_asked() {
+        let files = || vec![parsed("1a", "Game [0100000000010000][v0].nsp", "5")];
+
+        let service = service(RustfoilConfig {
+            embed_metadata: true,
+            ..RustfoilConfig::default()
+        });
+        let before = chrono::Utc::now();
+        let index = service.generate_index(files()).unwrap();
+
+        assert_eq!(
+            index.generator.as_deref(),
+            Some(concat!("rustfoil ", env!("CARGO_PKG_VERSION")))
+        );
+        let generated_at =
+            chrono::DateTime::parse_from_rfc3339(index.generated_at.as_deref().unwrap()).unwrap();
+        assert!(generated_at >= before && generated_at <= chrono::Utc::now());
+
+        let json: serde_json::Value =
+            serde_json::from_str(&index.to_json(service.config.schema).unwrap()).unwrap();
+        assert_eq!(json["generator"], index.generator.unwrap());
+        assert_eq!(json["generatedAt"], index.generated_at.unwrap());
+
+        let plain = self::service(RustfoilConfig::default());
+        let index = plain.generate_index(files()).unwrap();
+        assert!(index.generator.is_none() && index.generated_at.is_none());
+
+        let json: serde_json::Value =
+            serde_json::from_str(&index.to_json(plain.config.schema).unwrap()).unwrap();
+        assert!(json.get("generator").is_none() && json.get("generatedAt").is_none());
+    }
+
+    #[test]
+    fn cdn_fallback_is_appended_to_the_headers() {
+        let service = service(RustfoilConfig {
+            headers: Some(vec!["X-Api-Key=secret".to_string()]),
+            cdn_fallback: Some((
+                "X-Mirror".to_string(),
+                "https://cdn.example.com/".to_string(),
+            )),
+            ..RustfoilConfig::default()
+        });
+        let index = service
+            .generate_index(vec![parsed("1a", "Game [0100000000010000][v0].nsp", "5")])
+            .unwrap();
+
+        assert_eq!(
+            index.headers.unwrap(),
+            vec!["X-Api-Key: secret", "X-Mirror: https://cdn.example.com/"]
+        );
+    }
+
+    #[test]
+    fn checksum_sidecar_matches_the_written_index() {
+        let dir = temp_dir("checksum");
+        let path = dir.join("index.tfl");
+        let service = service(RustfoilConfig {
+            output_path: path.to_owned(),
+            checksum_sidecar: true,
+            ..RustfoilConfig::default()
+        });
+
+        let index = service
+            .generate_index(vec![parsed("1a", "Game [0100000000010000][v0].nsp", "5")])
+            .unwrap();
+        assert_eq!(service.output_index(&index).unwrap(), vec![path.to_owned()]);
+
+        let sidecar = std::fs::read_to_string(dir.join("index.tfl.sha256")).unwrap();
+        let digest = storage::hex(&Sha256::digest(&std::fs::read(&path).unwrap()));
+        assert_eq!(sidecar, format!("{}  index.tfl\n", digest));
+        assert_eq!(digest.len(), 64);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn dedupe_strategies_keep_the_first_of_each_key() {
+        let files = || {
+            vec![
+                parsed("1a", "Game [0100000000010000][v0].nsp", "5"),
+                parsed("2b", "Game [0100000000010000][v0].nsp", "5"),
+                parsed("3c", "Game (Copy) [0100000000010000][v0].nsz", "4"),
+                parsed("1a", "Renamed [0100000000020000][v0].nsp", "6"),
+                parsed("4d", "Homebrew.nro", "1"),
+                parsed("5e", "Homebrew.nro", "1"),
+            ]
+        };
+        let kept = |strategy: Option<DedupeStrategy>| {
+            self::service(RustfoilConfig {
+                dedupe_strategy: strategy,
+                ..RustfoilConfig::default()
+            })
+            .select_files(files())
+            .unwrap()
+            .into_iter()
+            .map(|file| file.id)
+            .collect::<Vec<_>>()
+        };
+
+        assert_eq!(kept(None), vec!["1a", "2b", "3c", "1a", "4d", "5e"]);
+        // Files without a Title ID are never duplicates by Title ID
+        assert_eq!(
+            kept(Some(DedupeStrategy::TitleId)),
+            vec!["1a", "1a", "4d", "5e"]
+        );
+        assert_eq!(
+            kept(Some(DedupeStrategy::Name)),
+            vec!["1a", "3c", "1a", "4d"]
+        );
+        assert_eq!(
+            kept(Some(DedupeStrategy::Url)),
+            vec!["1a", "2b", "3c", "4d", "5e"]
+        );
+    }
+
+    #[test]
+    fn url_list_has_one_line_per_entry() {
+        let (base_url, server) = crate::gdrive::tests::mock_drive(vec![
+            "{}",
+            r#"{"files":[{"id":"1a","name":"Game [0100000000010000][v0].nsp","size":"5"},{"id":"2b","name":"Other [0100000000020000][v0].nsz","size":"7"},{"id":"3c","name":"Homebrew.nro","size":"1"}]}"#,
+        ]);
+        let dir = temp_dir("url-list");
+        let list_path = dir.join("urls.txt");
+
+        let mut service = service(RustfoilConfig {
+            folder_ids: vec!["folder".to_string()],
+            no_recursion: true,
+            output_path: dir.join("index.tfl"),
+            urls_only: Some(list_path.to_owned()),
+            no_index: true,
+            ..RustfoilConfig::default()
+        });
+        service.gdrive = Some(crate::gdrive::tests::gdrive(base_url, Arc::default()));
+
+        let (files, paths) = service.index_files(None).unwrap();
+        assert_eq!(server.join().unwrap().len(), 2);
+        assert!(paths.is_empty());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let list = std::fs::read_to_string(&list_path).unwrap();
+        let index = service.generate_index(files).unwrap();
+        assert_eq!(list.lines().count(), 3);
+        assert_eq!(list.lines().collect::<Vec<_>>(), urls(&index));
+        assert!(list.ends_with(".nro\n"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn total_size_cap_keeps_the_newest_files_within_the_budget() {
+        let file = |id: &str, size: &str, modified_time: Option<&str>| ParsedFileInfo {
+            modified_time: modified_time.map(str::to_string),
+            ..parsed(id, format!("{}.nsp", id).as_str(), size)
+        };
+        let files = || {
+            vec![
+                file("old", "40", Some("2024-01-01T00:00:00Z")),
+                file("new", "50", Some("2024-03-01T00:00:00Z")),
+                file("unknown", "5", None),
+                file("mid", "30", Some("2024-02-01T00:00:00Z")),
+            ]
+        };
+        let kept = |budget: u64| {
+            self::service(RustfoilConfig {
+                max_total_size: Some(budget),
+                ..RustfoilConfig::default()
+            })
+            .select_files(files())
+            .unwrap()
+            .into_iter()
+            .map(|file| file.id)
+            .collect::<Vec<_>>()
+        };
+
+        // Kept files stay in their order
+        assert_eq!(kept(125), vec!["old", "new", "unknown", "mid"]);
+        assert_eq!(kept(124), vec!["old", "new", "mid"]);
+        assert_eq!(kept(80), vec!["new", "mid"]);
+        // The cutoff stops at the first file over the budget, smaller older ones aren't added
+        assert_eq!(kept(79), vec!["new"]);
+        assert!(kept(49).is_empty());
+    }
+}
@@ -1,41 +1,270 @@
-use crate::compression::CompressionFlag;
-use crate::encryption::EncryptionFlag;
+use crate::compression::{CompressionFlag, ZstdOptions};
+use crate::encryption::{self, EncryptionFlag, RsaPadding, AES_KEY_SIZE, WRAPPED_KEY_SIZE};
+use crate::error::RustfoilError;
 use crate::result;
-use std::borrow::Borrow;
-use std::io::Write;
-use std::path::PathBuf;
+use std::convert::TryInto;
+use std::path::{Path, PathBuf};
+
+/// Magic every Tinfoil index file starts with
+pub const MAGIC: &[u8; 7] = b"TINFOIL";
+/// Size of the header, the (padded & possibly encrypted) data follows right after it
+pub const HEADER_SIZE: usize = 0x110;
+const FLAG_OFFSET: usize = 0x7;
+const SESSION_KEY_OFFSET: usize = 0x8;
+const DATA_LENGTH_OFFSET: usize = SESSION_KEY_OFFSET + WRAPPED_KEY_SIZE;
+/// AES block size the compressed data is padded to
+const BLOCK_SIZE: usize = 0x10;
+
+/// Header of a Tinfoil index file: the magic, one flag byte combining compression (low nibble) &
+/// encryption (high nibble), the RSA wrapped session key & the little-endian length of the
+/// compressed data before padding.
+///
+/// Tinfoil reads the data by decrypting it, cutting it to `data_length` & decompressing that, so
+/// it has to be written by compressing, padding & encrypting in exactly this order
+pub struct Header {
+    pub compression: CompressionFlag,
+    pub encryption: EncryptionFlag,
+    /// Zeroes if the data isn't encrypted
+    pub session_key: Vec<u8>,
+    pub data_length: usize,
+}
+
+impl Header {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE);
+
+        bytes.extend_from_slice(MAGIC);
+        bytes.push((self.compression as u8) | (self.encryption as u8));
+        bytes.extend_from_slice(self.session_key.as_slice());
+        bytes.extend_from_slice(&(self.data_length as u64).to_le_bytes());
+
+        assert_eq!(bytes.len(), HEADER_SIZE, "session key has the wrong size");
+
+        bytes
+    }
+
+    pub fn parse(bytes: &[u8]) -> result::Result<Header> {
+        let invalid = |reason: &str| RustfoilError::InvalidTinfoilFile(reason.to_string());
+
+        if bytes.len() < HEADER_SIZE || &bytes[..FLAG_OFFSET] != MAGIC {
+            return Err(invalid("missing TINFOIL header").into());
+        }
+
+        let flag = bytes[FLAG_OFFSET];
+
+        Ok(Header {
+            compression: CompressionFlag::from_flag(flag)
+                .ok_or_else(|| invalid("unknown compression"))?,
+            encryption: EncryptionFlag::from_flag(flag)
+                .ok_or_else(|| invalid("unknown encryption"))?,
+            session_key: bytes[SESSION_KEY_OFFSET..DATA_LENGTH_OFFSET].to_vec(),
+            data_length: u64::from_le_bytes(bytes[DATA_LENGTH_OFFSET..HEADER_SIZE].try_into()?)
+                as usize,
+        })
+    }
+}
+
+/// Zero-pads the data to a multiple of the AES block size. Like Tinfoil's own tooling a full
+/// block is added when the data already is a multiple of it
+pub fn pad(data: &mut Vec<u8>) {
+    data.resize(data.len() + BLOCK_SIZE - (data.len() % BLOCK_SIZE), 0);
+}
 
 pub fn convert_to_tinfoil_format(
     json: &str,
     compression: CompressionFlag,
     encryption: EncryptionFlag,
     encryption_file_path_buf: Option<PathBuf>,
+    session_key: Option<&[u8; AES_KEY_SIZE]>,
+    padding: RsaPadding,
+    zstd_options: ZstdOptions,
 ) -> result::Result<Vec<u8>> {
-    let mut data = compression.compress(json)?;
+    let (compression, mut data) = compression.compress_resolved(json, zstd_options)?;
     let data_length = data.len();
 
-    data.append(&mut b"\x00".repeat(0x10 - (data_length % 0x10)));
+    pad(&mut data);
 
     let session_key = match encryption {
-        EncryptionFlag::NoEncrypt => Some(b"\x00".repeat(0x100)),
+        EncryptionFlag::NoEncrypt => vec![0; WRAPPED_KEY_SIZE],
         EncryptionFlag::Encrypt => {
-            let (encrypted_data, encryption_key) =
-                encryption.encrypt(data, encryption_file_path_buf.unwrap().as_path())?;
+            let (encrypted_data, encryption_key) = encryption::encrypt(
+                &data,
+                encryption_file_path_buf.unwrap().as_path(),
+                session_key,
+                padding,
+            )?;
 
             data = encrypted_data;
-            Some(encryption_key)
+            encryption_key
         }
     };
 
-    let mut bytes = Vec::new();
+    let mut bytes = Header {
+        compression,
+        encryption,
+        session_key,
+        data_length,
+    }
+    .to_bytes();
 
-    let flag = (compression as u8) | (encryption as u8);
-
-    bytes.write(b"TINFOIL")?;
-    bytes.write(flag.to_le_bytes().borrow())?;
-    bytes.write(session_key.unwrap().as_slice())?;
-    bytes.write(data_length.to_le_bytes().borrow())?;
-    bytes.write(data.borrow())?;
+    bytes.append(&mut data);
 
     Ok(bytes)
 }
+
+/// Decodes a Tinfoil index file back into its JSON, encrypted files need the private key. The
+/// steps of `convert_to_tinfoil_format` are reversed: decrypt, cut the padding, decompress
+pub fn decode_tinfoil_format(
+    bytes: &[u8],
+    private_key: Option<&Path>,
+    padding: RsaPadding,
+) -> result::Result<String> {
+    let invalid = |reason: &str| RustfoilError::InvalidTinfoilFile(reason.to_string());
+
+    let header = Header::parse(bytes)?;
+    let mut data = bytes[HEADER_SIZE..].to_vec();
+
+    if let EncryptionFlag::Encrypt = header.encryption {
+        let private_key =
+            private_key.ok_or_else(|| invalid("file is encrypted, a private key is needed"))?;
+        data = encryption::decrypt(&data, header.session_key.as_slice(), private_key, padding)?;
+    }
+
+    if header.data_length > data.len() {
+        return Err(invalid("data is shorter than announced in the header").into());
+    }
+
+    data.truncate(header.data_length);
+
+    Ok(String::from_utf8(header.compression.decompress(&data)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An index dominated by names sharing long prefixes
+    fn similar_names_index() -> String {
+        let files: Vec<String> = (0..500)
+            .map(|n| {
+                format!(
+                    r#"{{"url":"gdrive:1AbCdEfGhIjKlMnOpQrStUvWxYz{:05}#Super%20Long%20Game%20Series%20Collection%20Part%20{}%20[01000000000{:05}][v0].nsp","size":{}}}"#,
+                    n,
+                    n,
+                    n,
+                    n * 1024
+                )
+            })
+            .collect();
+
+        format!(r#"{{"files":[{}]}}"#, files.join(","))
+    }
+
+    fn testdata(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join(name)
+    }
+
+    #[test]
+    fn padding_always_adds_up_to_a_full_block() {
+        for (length, padded) in [(0, 16), (1, 16), (15, 16), (16, 32), (17, 32)] {
+            let mut data = vec![1; length];
+            pad(&mut data);
+
+            assert_eq!(data.len(), padded);
+            assert!(data[length..].iter().all(|&byte| byte == 0));
+        }
+    }
+
+    #[test]
+    fn header_has_tinfoils_byte_layout() {
+        let bytes = Header {
+            compression: CompressionFlag::Zstd,
+            encryption: EncryptionFlag::Encrypt,
+            session_key: vec![0xAB; WRAPPED_KEY_SIZE],
+            data_length: 0x0102,
+        }
+        .to_bytes();
+
+        assert_eq!(bytes.len(), HEADER_SIZE);
+        assert_eq!(&bytes[..7], b"TINFOIL");
+        assert_eq!(bytes[7], 0xFD);
+        assert!(bytes[8..0x108].iter().all(|&byte| byte == 0xAB));
+        assert_eq!(&bytes[0x108..], &[0x02, 0x01, 0, 0, 0, 0, 0, 0]);
+
+        let header = Header::parse(&bytes).unwrap();
+        assert!(matches!(header.compression, CompressionFlag::Zstd));
+        assert!(matches!(header.encryption, EncryptionFlag::Encrypt));
+        assert_eq!(header.session_key, vec![0xAB; WRAPPED_KEY_SIZE]);
+        assert_eq!(header.data_length, 0x0102);
+
+        let mut unknown = bytes.to_owned();
+        unknown[7] = 0x0A;
+        for invalid in [&bytes[..HEADER_SIZE - 1], &bytes[1..], &unknown[..]] {
+            assert!(Header::parse(invalid).is_err());
+        }
+    }
+
+    #[test]
+    fn data_is_compressed_padded_then_encrypted() {
+        let json = similar_names_index();
+        let session_key = [7; AES_KEY_SIZE];
+        let public_key = testdata("test_key.pub.pem");
+
+        let bytes = convert_to_tinfoil_format(
+            json.as_str(),
+            CompressionFlag::Zstd,
+            EncryptionFlag::Encrypt,
+            Some(public_key.to_owned()),
+            Some(&session_key),
+            RsaPadding::Oaep,
+            ZstdOptions::default(),
+        )
+        .unwrap();
+
+        let mut compressed = CompressionFlag::Zstd.compress(json.as_str()).unwrap();
+        let header = Header::parse(&bytes).unwrap();
+        assert_eq!(header.data_length, compressed.len());
+
+        pad(&mut compressed);
+        assert_eq!(
+            &bytes[HEADER_SIZE..],
+            encryption::encrypt_aes(&compressed, &session_key)
+                .unwrap()
+                .as_slice()
+        );
+    }
+
+    #[test]
+    fn compressed_and_encrypted_files_round_trip() {
+        let json = similar_names_index();
+        let public_key = testdata("test_key.pub.pem");
+        let private_key = testdata("test_key.pem");
+
+        for compression in [
+            CompressionFlag::Off,
+            CompressionFlag::Zstd,
+            CompressionFlag::Zlib,
+        ] {
+            for padding in [RsaPadding::Oaep, RsaPadding::Pkcs1v15] {
+                let bytes = convert_to_tinfoil_format(
+                    json.as_str(),
+                    compression,
+                    EncryptionFlag::Encrypt,
+                    Some(public_key.to_owned()),
+                    None,
+                    padding,
+                    ZstdOptions::default(),
+                )
+                .unwrap();
+
+                assert_eq!(
+                    decode_tinfoil_format(&bytes, Some(private_key.as_path()), padding).unwrap(),
+                    json
+                );
+                assert!(decode_tinfoil_format(&bytes, None, padding).is_err());
+            }
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use crate::compression;
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use yup_oauth2::{Token, TokenStorage};
+
+/// Numbers the temp files of concurrent writes within the process
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Single stored token, same layout as the `token.json` written by yup-oauth2
+#[derive(Clone, Serialize, Deserialize)]
+struct StoredToken {
+    hash: u64,
+    scopes: Option<Vec<String>>,
+    token: Token,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredTokens {
+    tokens: Vec<StoredToken>,
+}
+
+/// OAuth token storage which is either backed by a token file or by JSON contents passed in directly
+pub struct TokenStore {
+    location: Option<PathBuf>,
+    /// Whether the token file was gzip-compressed, so it's written back the same way
+    gzipped: bool,
+    tokens: Vec<StoredToken>,
+}
+
+impl TokenStore {
+    /// Loads tokens from the given file, which is allowed to not exist yet & may be gzip-compressed
+    pub fn from_file(path: &Path) -> io::Result<TokenStore> {
+        let (tokens, gzipped) = match std::fs::read(path) {
+            Ok(data) => {
+                let gzipped = compression::is_gzip(&data);
+                let json = compression::decode_maybe_gzip(data)?;
+                (TokenStore::parse(json.as_str())?, gzipped)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                (Vec::new(), path.extension().is_some_and(|ext| ext == "gz"))
+            }
+            Err(e) => return Err(e),
+        };
+
+        Ok(TokenStore {
+            location: Some(path.to_path_buf()),
+            gzipped,
+            tokens,
+        })
+    }
+
+    /// Loads tokens from JSON contents, newly obtained tokens are kept in memory only
+    pub fn from_json(json: &str) -> io::Result<TokenStore> {
+        Ok(TokenStore {
+            location: None,
+            gzipped: false,
+            tokens: TokenStore::parse(json)?,
+        })
+    }
+
+    fn parse(json: &str) -> io::Result<Vec<StoredToken>> {
+        let stored: StoredTokens = serde_json::from_str(json)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        Ok(stored.tokens)
+    }
+
+    /// Writes the tokens back to the token file, e.g. after a refresh. The file is replaced
+    /// atomically, so an interrupted write can't lose the refresh token
+    fn persist(&self) -> io::Result<()> {
+        if let Some(location) = &self.location {
+            let json = serde_json::to_string(&StoredTokens {
+                tokens: self.tokens.to_owned(),
+            })
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+            let data = if self.gzipped {
+                compression::gzip(json.as_bytes()).map_err(|e| io::Error::other(e.to_string()))?
+            } else {
+                json.into_bytes()
+            };
+
+            // Every service has its own store of the same file, e.g. the share workers, so each
+            // write gets its own temp file & the last rename wins
+            let mut temp_path = location.to_owned().into_os_string();
+            temp_path.push(format!(
+                ".{}.{}.tmp",
+                std::process::id(),
+                TEMP_FILE_COUNTER.fetch_add(1, Ordering::SeqCst)
+            ));
+            let temp_path = PathBuf::from(temp_path);
+
+            if let Err(e) =
+                std::fs::write(&temp_path, data).and_then(|_| std::fs::rename(&temp_path, location))
+            {
+                let _ = std::fs::remove_file(&temp_path);
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl TokenStorage for TokenStore {
+    type Error = io::Error;
+
+    fn set(
+        &mut self,
+        scope_hash: u64,
+        scopes: &Vec<&str>,
+        token: Option<Token>,
+    ) -> Result<(), Self::Error> {
+        let mut sorted = scopes.to_owned();
+        sorted.sort_unstable();
+
+        // Hashes of token files written by another build may differ, so the scopes count too
+        self.tokens.retain(|stored| {
+            stored.hash != scope_hash
+                && stored.scopes.as_ref().is_none_or(|stored_scopes| {
+                    let mut stored_scopes: Vec<&str> =
+                        stored_scopes.iter().map(String::as_str).collect();
+                    stored_scopes.sort_unstable();
+                    stored_scopes != sorted
+                })
+        });
+
+        if let Some(token) = token {
+            self.tokens.push(StoredToken {
+                hash: scope_hash,
+                scopes: Some(scopes.iter().map(|scope| scope.to_string()).collect()),
+                token,
+            });
+        }
+
+        self.persist()
+    }
+
+    fn get(&self, scope_hash: u64, scopes: &Vec<&str>) -> Result<Option<Token>, Self::Error> {
+        let mut scopes = scopes.to_owned();
+        scopes.sort_unstable();
+        scopes.dedup();
+
+        for stored in &self.tokens {
+            let matches = match &stored.scopes {
+                Some(stored_scopes) => {
+                    stored_scopes
+                        .iter()
+                        .filter(|scope| scopes.contains(&scope.as_str()))
+                        .count()
+                        >= scopes.len()
+                }
+                None => stored.hash == scope_hash,
+            };
+
+            if matches {
+                return Ok(Some(stored.token.to_owned()));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCOPE: &str = "https://www.googleapis.com/auth/drive";
+
+    fn token(access_token: &str) -> Token {
+        Token {
+            access_token: access_token.to_string(),
+            refresh_token: "refresh".to_string(),
+            token_type: "Bearer".to_string(),
+            expires_in: None,
+            expires_in_timestamp: Some(i64::MAX / 2),
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("rustfoil-token-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn concurrent_stores_of_one_file_keep_it_valid() {
+        let dir = temp_dir("concurrent");
+        let path = dir.join("token.json");
+
+        std::thread::scope(|scope| {
+            for worker in 0..8 {
+                let path = path.as_path();
+
+                scope.spawn(move || {
+                    let mut store = TokenStore::from_file(path).unwrap();
+
+                    for refresh in 0..20 {
+                        let access_token = format!("{}-{}", worker, refresh);
+                        store
+                            .set(1, &vec![SCOPE], Some(token(&access_token)))
+                            .unwrap();
+                    }
+                });
+            }
+        });
+
+        let store = TokenStore::from_file(path.as_path()).unwrap();
+        assert!(store.get(1, &vec![SCOPE]).unwrap().is_some());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn plain_and_gzipped_token_files_are_read_and_written_back_alike() {
+        let dir = temp_dir("gzip");
+        let json = serde_json::to_string(&StoredTokens {
+            tokens: vec![StoredToken {
+                hash: 1,
+                scopes: Some(vec![SCOPE.to_string()]),
+                token: token("stored"),
+            }],
+        })
+        .unwrap();
+
+        let plain = dir.join("token.json");
+        let gzipped = dir.join("token.json.gz");
+        std::fs::write(&plain, &json).unwrap();
+        std::fs::write(&gzipped, compression::gzip(json.as_bytes()).unwrap()).unwrap();
+
+        for (path, is_gzip) in [(&plain, false), (&gzipped, true)] {
+            let mut store = TokenStore::from_file(path).unwrap();
+            let stored = store.get(1, &vec![SCOPE]).unwrap().unwrap();
+            assert_eq!(stored.access_token, "stored");
+
+            store
+                .set(1, &vec![SCOPE], Some(token("refreshed")))
+                .unwrap();
+            let data = std::fs::read(path).unwrap();
+            assert_eq!(compression::is_gzip(&data), is_gzip);
+
+            let store = TokenStore::from_file(path).unwrap();
+            let refreshed = store.get(1, &vec![SCOPE]).unwrap().unwrap();
+            assert_eq!(refreshed.access_token, "refreshed");
+        }
+
+        // A new token file is compressed if it's named like it
+        let new = dir.join("new.json.gz");
+        let mut store = TokenStore::from_file(new.as_path()).unwrap();
+        store.set(1, &vec![SCOPE], Some(token("new"))).unwrap();
+        assert!(compression::is_gzip(&std::fs::read(&new).unwrap()));
+
+        std::fs::write(&plain, b"not json").unwrap();
+        assert_eq!(
+            TokenStore::from_file(plain.as_path()).err().unwrap().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}